@@ -1,6 +1,7 @@
 //! Asset loading
 
 use crate::AppState;
+use crate::theme::{FontKey, Theme};
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 
@@ -14,6 +15,59 @@ pub struct ModelAssets {
 pub struct UiAssets {
     #[asset(path = "fonts/Nunito-Black.ttf")]
     pub button_font: Handle<Font>,
+    #[asset(path = "fonts/Nunito-Regular.ttf")]
+    pub body_font: Handle<Font>,
+    #[asset(path = "fonts/Knewave-Regular.ttf")]
+    pub display_font: Handle<Font>,
+    #[asset(path = "themes/default.theme.ron")]
+    pub default_theme: Handle<Theme>,
+    #[asset(path = "themes/high_contrast.theme.ron")]
+    pub high_contrast_theme: Handle<Theme>,
+}
+
+impl UiAssets {
+    pub fn font(&self, key: FontKey) -> Handle<Font> {
+        match key {
+            FontKey::NunitoBlack => self.button_font.clone(),
+            FontKey::NunitoRegular => self.body_font.clone(),
+            FontKey::Knewave => self.display_font.clone(),
+        }
+    }
+
+    /// All selectable themes, in the order they are cycled through in the options menu.
+    pub fn themes(&self) -> [&Handle<Theme>; 2] {
+        [&self.default_theme, &self.high_contrast_theme]
+    }
+}
+
+#[derive(AssetCollection, Resource, Debug)]
+pub struct AudioAssets {
+    #[asset(path = "audio/music/menu.wav")]
+    pub menu_music: Handle<AudioSource>,
+    /// Background music for each world, the first number of a level name
+    #[asset(
+        paths(
+            "audio/music/world1.wav",
+            "audio/music/world2.wav",
+            "audio/music/world3.wav"
+        ),
+        collection(typed)
+    )]
+    pub world_music: Vec<Handle<AudioSource>>,
+    #[asset(path = "audio/sfx/hover.wav")]
+    pub hover: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/press.wav")]
+    pub press: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/rotate.wav")]
+    pub rotate: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/invalid.wav")]
+    pub invalid: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/flow.wav")]
+    pub flow: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/win.wav")]
+    pub win: Handle<AudioSource>,
+    #[asset(path = "audio/sfx/fail.wav")]
+    pub fail: Handle<AudioSource>,
 }
 
 pub struct AssetsPlugin;
@@ -24,7 +78,8 @@ impl Plugin for AssetsPlugin {
             LoadingState::new(AppState::LoadingAssets)
                 .continue_to_state(AppState::MainMenu)
                 .load_collection::<ModelAssets>()
-                .load_collection::<UiAssets>(),
+                .load_collection::<UiAssets>()
+                .load_collection::<AudioAssets>(),
         );
     }
 }
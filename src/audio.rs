@@ -0,0 +1,179 @@
+//! Music and sound effects
+
+use crate::AppState;
+use crate::assets::AudioAssets;
+use crate::game::PipeGameState;
+use crate::level::{CurrentLevel, Level, LevelInLoading};
+use crate::options::Settings;
+use bevy::audio::Volume;
+use bevy::prelude::*;
+
+pub struct GameAudioPlugin;
+
+impl Plugin for GameAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySfx>()
+            .add_systems(
+                Update,
+                (
+                    play_music
+                        .run_if(resource_exists::<AudioAssets>.and(state_changed::<AppState>)),
+                    play_sfx.run_if(on_event::<PlaySfx>),
+                    apply_volume.run_if(resource_changed::<Settings>),
+                ),
+            )
+            .add_systems(OnEnter(PipeGameState::Flowing), start_flow_loop)
+            .add_systems(OnExit(PipeGameState::Flowing), stop_flow_loop)
+            .add_systems(OnEnter(PipeGameState::LevelWon), play_win_stinger)
+            .add_systems(OnEnter(PipeGameState::LevelFailed), play_fail_stinger);
+    }
+}
+
+/// Volume group an audio entity belongs to.
+#[derive(Component, Debug, Clone, Copy)]
+pub enum AudioBus {
+    Music,
+    Sfx,
+}
+
+impl AudioBus {
+    fn volume(self, settings: &Settings) -> Volume {
+        let bus = match self {
+            AudioBus::Music => settings.music_volume,
+            AudioBus::Sfx => settings.sfx_volume,
+        };
+        Volume::Linear(settings.master_volume * bus)
+    }
+}
+
+/// Background music currently playing.
+#[derive(Component, Debug)]
+struct MusicTrack(Handle<AudioSource>);
+
+/// Flowing/gurgling loop played while fluid is moving.
+#[derive(Component, Debug)]
+struct FlowLoop;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Sfx {
+    ButtonHover,
+    ButtonPress,
+    PipeRotate,
+    /// A move that isn't allowed, like dropping a pipe on a locked one
+    Invalid,
+    Win,
+    Fail,
+}
+
+/// Event for playing a one-shot sound effect.
+#[derive(Event, Debug)]
+pub struct PlaySfx(pub Sfx);
+
+/// Switches the music to the track for the current screen, keeping it going if it is already playing.
+fn play_music(
+    mut commands: Commands,
+    app_state: Res<State<AppState>>,
+    audio: Res<AudioAssets>,
+    level_in_loading: Option<Res<LevelInLoading>>,
+    current_level: Option<Res<CurrentLevel>>,
+    tracks: Query<(Entity, &MusicTrack)>,
+    settings: Res<Settings>,
+) {
+    let track = match app_state.get() {
+        AppState::LoadingAssets => None,
+        AppState::MainMenu | AppState::Options | AppState::LevelSelect => {
+            Some(audio.menu_music.clone())
+        }
+        AppState::LoadingLevel | AppState::InGame => {
+            // While loading, the current level is still the one that was left
+            let level = match app_state.get() {
+                AppState::LoadingLevel => level_in_loading.map(|loading| loading.0.clone()),
+                _ => current_level.map(|current| current.0.clone()),
+            };
+            let world = level.as_ref().and_then(world_of).unwrap_or(1usize);
+            audio
+                .world_music
+                .get(world.saturating_sub(1) % audio.world_music.len().max(1))
+                .cloned()
+        }
+    };
+
+    for (entity, playing) in &tracks {
+        if Some(&playing.0) == track.as_ref() {
+            return;
+        }
+        commands.entity(entity).despawn();
+    }
+
+    if let Some(track) = track {
+        commands.spawn((
+            AudioPlayer::new(track.clone()),
+            PlaybackSettings::LOOP.with_volume(AudioBus::Music.volume(&settings)),
+            AudioBus::Music,
+            MusicTrack(track),
+        ));
+    }
+}
+
+/// World of a level, the first number of its file name, which looks like "<world>-<level>.tmx".
+fn world_of(level: &Handle<Level>) -> Option<usize> {
+    let path = level.path()?;
+    let name = path.path().file_stem()?.to_str()?;
+    name.split('-').next()?.parse().ok()
+}
+
+fn play_sfx(
+    mut commands: Commands,
+    mut events: EventReader<PlaySfx>,
+    audio: Option<Res<AudioAssets>>,
+    settings: Res<Settings>,
+) {
+    let Some(audio) = audio else {
+        return;
+    };
+    for PlaySfx(sfx) in events.read() {
+        let source = match sfx {
+            Sfx::ButtonHover => &audio.hover,
+            Sfx::ButtonPress => &audio.press,
+            Sfx::PipeRotate => &audio.rotate,
+            Sfx::Invalid => &audio.invalid,
+            Sfx::Win => &audio.win,
+            Sfx::Fail => &audio.fail,
+        };
+        commands.spawn((
+            AudioPlayer::new(source.clone()),
+            PlaybackSettings::DESPAWN.with_volume(AudioBus::Sfx.volume(&settings)),
+            AudioBus::Sfx,
+        ));
+    }
+}
+
+fn start_flow_loop(mut commands: Commands, audio: Res<AudioAssets>, settings: Res<Settings>) {
+    commands.spawn((
+        AudioPlayer::new(audio.flow.clone()),
+        PlaybackSettings::LOOP.with_volume(AudioBus::Sfx.volume(&settings)),
+        AudioBus::Sfx,
+        FlowLoop,
+    ));
+}
+
+fn stop_flow_loop(mut commands: Commands, loops: Query<Entity, With<FlowLoop>>) {
+    for entity in &loops {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn play_win_stinger(mut sfx: EventWriter<PlaySfx>) {
+    sfx.write(PlaySfx(Sfx::Win));
+}
+
+fn play_fail_stinger(mut sfx: EventWriter<PlaySfx>) {
+    sfx.write(PlaySfx(Sfx::Fail));
+}
+
+/// Applies volume changes from the settings to everything that is playing.
+fn apply_volume(settings: Res<Settings>, mut sinks: Query<(&AudioBus, &mut AudioSink)>) {
+    for (bus, mut sink) in &mut sinks {
+        sink.set_volume(bus.volume(&settings));
+    }
+}
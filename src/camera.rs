@@ -0,0 +1,307 @@
+//! Level camera framing and controls
+
+use crate::AppState;
+use bevy::input::gestures::PinchGesture;
+use bevy::input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit};
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowResized};
+
+pub struct LevelCameraPlugin;
+
+impl Plugin for LevelCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                (zoom_camera, pan_camera, tilt_camera, nudge_camera),
+                clamp_camera,
+                frame_level_camera,
+            )
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+/// Camera looking at the level grid.
+///
+/// With the default zoom and focus the whole grid is in view; the player can zoom in, pan and
+/// tilt from there. A new camera is spawned for every level, so the view resets between levels.
+#[derive(Component, Debug)]
+pub struct LevelCamera {
+    /// Grid bounds on the XZ plane
+    pub bounds: Rect,
+    /// Point on the grid plane the camera looks at
+    pub focus: Vec2,
+    /// Zoom factor, 1 fits the whole grid
+    pub zoom: f32,
+    /// Blend from the default angled view (0) to straight top-down (1)
+    pub tilt: f32,
+    /// Tilt the camera is easing towards
+    pub target_tilt: f32,
+    /// Point the focus is easing towards, to show the player something that happened there
+    pub nudge: Option<Vec2>,
+}
+
+impl LevelCamera {
+    pub fn new(bounds: Rect) -> Self {
+        LevelCamera {
+            bounds,
+            focus: bounds.center(),
+            zoom: 1.,
+            tilt: 0.,
+            target_tilt: 0.,
+            nudge: None,
+        }
+    }
+
+    /// Nearest point to `point` the camera can focus on at the given zoom.
+    pub fn reachable_focus(&self, point: Vec2, zoom: f32) -> Vec2 {
+        let reach = self.bounds.half_size() * (1. - 1. / zoom);
+        let center = self.bounds.center();
+        point.clamp(center - reach, center + reach)
+    }
+
+    pub fn view_direction(&self) -> Vec3 {
+        default_view_direction()
+            .lerp(Vec3::NEG_Y, self.tilt)
+            .normalize()
+    }
+}
+
+/// Height of the pipe models above the grid plane, included when fitting the view.
+const CONTENT_HEIGHT: f32 = 1.;
+
+/// Extra space around the grid, as a fraction of the viewport.
+const FRAMING_MARGIN: f32 = 0.05;
+
+const MAX_ZOOM: f32 = 4.;
+
+/// Zoom change per scroll wheel line.
+const SCROLL_ZOOM_STEP: f32 = 0.1;
+
+/// Distance from the window border, in pixels, at which the cursor starts panning.
+const EDGE_PAN_MARGIN: f32 = 16.;
+
+/// Edge and stick pan speed, in viewport heights per second.
+const PAN_SPEED: f32 = 0.8;
+
+/// Tilt change per second while easing between views.
+const TILT_SPEED: f32 = 3.;
+
+/// Fraction of the remaining distance the focus moves per second while nudged.
+const NUDGE_SPEED: f32 = 4.;
+
+/// Zoom a nudge eases in to at least, so the nudged point is close enough to see.
+const NUDGE_ZOOM: f32 = 1.5;
+
+/// Direction of the default angled top-down view.
+pub fn default_view_direction() -> Vec3 {
+    Vec3::new(0., -15., -5.).normalize()
+}
+
+fn zoom_camera(
+    mut cameras: Query<&mut LevelCamera>,
+    scroll: Res<AccumulatedMouseScroll>,
+    mut pinch: EventReader<PinchGesture>,
+    gamepads: Query<&Gamepad>,
+    time: Res<Time>,
+) {
+    let lines = match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / 100.,
+    };
+    let mut factor = 1. + lines * SCROLL_ZOOM_STEP;
+    for event in pinch.read() {
+        factor *= 1. + event.0;
+    }
+    for gamepad in &gamepads {
+        factor *= 1. + gamepad.right_stick().y * time.delta_secs();
+    }
+    if factor == 1. {
+        return;
+    }
+
+    for mut camera in &mut cameras {
+        camera.zoom = (camera.zoom * factor).clamp(1., MAX_ZOOM);
+    }
+}
+
+/// Pans with a right-drag, the left stick, or the cursor at the window border.
+///
+/// Panning takes over from a nudge, but a cursor that was already at the border when the nudge
+/// started has to leave and come back first.
+fn pan_camera(
+    mut cameras: Query<(&mut LevelCamera, &Projection, &Transform)>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mouse_motion: Res<AccumulatedMouseMotion>,
+    gamepads: Query<&Gamepad>,
+    time: Res<Time>,
+    mut at_edge: Local<bool>,
+) {
+    let Ok(window) = windows.single() else {
+        return;
+    };
+
+    // Pan in screen space, in pixels: +x is right, +y is down.
+    let mut pan = Vec2::ZERO;
+    let mut took_over = false;
+    if mouse_buttons.pressed(MouseButton::Right) {
+        pan -= mouse_motion.delta;
+        took_over |= pan != Vec2::ZERO;
+    } else if let Some(cursor) = window.cursor_position() {
+        let size = window.size();
+        let speed = PAN_SPEED * size.y * time.delta_secs();
+        if cursor.x < EDGE_PAN_MARGIN {
+            pan.x -= speed;
+        } else if cursor.x > size.x - EDGE_PAN_MARGIN {
+            pan.x += speed;
+        }
+        if cursor.y < EDGE_PAN_MARGIN {
+            pan.y -= speed;
+        } else if cursor.y > size.y - EDGE_PAN_MARGIN {
+            pan.y += speed;
+        }
+        let entered = pan != Vec2::ZERO && !*at_edge;
+        *at_edge = pan != Vec2::ZERO;
+        took_over |= entered;
+    } else {
+        *at_edge = false;
+    }
+    for gamepad in &gamepads {
+        let stick = gamepad.left_stick();
+        took_over |= stick != Vec2::ZERO;
+        pan += Vec2::new(stick.x, -stick.y) * PAN_SPEED * window.height() * time.delta_secs();
+    }
+    if pan == Vec2::ZERO {
+        return;
+    }
+
+    for (mut camera, projection, transform) in &mut cameras {
+        // Only the player's own input takes over from a nudge
+        if took_over {
+            camera.nudge = None;
+        } else if camera.nudge.is_some() {
+            continue;
+        }
+        let Projection::Perspective(perspective) = projection else {
+            continue;
+        };
+        // World units per pixel at the focus point
+        let distance = transform
+            .translation
+            .distance(camera.focus.extend(0.).xzy());
+        let scale = 2. * distance * (perspective.fov / 2.).tan() / window.height();
+        let right = transform.right().xz().normalize_or_zero();
+        let down = -Vec3::from(transform.up()).xz().normalize_or_zero();
+        camera.focus += (right * pan.x + down * pan.y) * scale;
+    }
+}
+
+fn tilt_camera(
+    mut cameras: Query<&mut LevelCamera>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    time: Res<Time>,
+) {
+    let toggle = keys.just_pressed(KeyCode::KeyT)
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.just_pressed(GamepadButton::North));
+
+    for mut camera in &mut cameras {
+        if toggle {
+            camera.target_tilt = 1. - camera.target_tilt;
+        }
+        if camera.tilt != camera.target_tilt {
+            let step = TILT_SPEED * time.delta_secs();
+            camera.tilt += (camera.target_tilt - camera.tilt).clamp(-step, step);
+        }
+    }
+}
+
+/// Eases the focus to a nudged point, zooming in if needed to get it closer.
+fn nudge_camera(mut cameras: Query<&mut LevelCamera>, time: Res<Time>) {
+    for mut camera in &mut cameras {
+        let Some(target) = camera.nudge else {
+            continue;
+        };
+        let zoom = camera.zoom.max(NUDGE_ZOOM);
+        let target = camera.reachable_focus(target, zoom);
+        if camera.focus.distance(target) < 0.01 && zoom - camera.zoom < 0.01 {
+            camera.nudge = None;
+            continue;
+        }
+        let step = (NUDGE_SPEED * time.delta_secs()).min(1.);
+        camera.zoom += (zoom - camera.zoom) * step;
+        camera.focus = camera.focus.lerp(target, step);
+    }
+}
+
+/// Keeps the focus point within the part of the grid that can be reached at the current zoom.
+fn clamp_camera(mut cameras: Query<&mut LevelCamera>) {
+    for mut camera in &mut cameras {
+        let focus = camera.reachable_focus(camera.focus, camera.zoom);
+        if camera.focus != focus {
+            camera.focus = focus;
+        }
+    }
+}
+
+fn frame_level_camera(
+    mut cameras: Query<(Ref<LevelCamera>, &Projection, &mut Transform)>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut resized: EventReader<WindowResized>,
+) {
+    let resized = resized.read().count() > 0;
+    let Ok(window) = windows.single() else {
+        return;
+    };
+    if window.height() <= 0. {
+        return;
+    }
+    let aspect = window.width() / window.height();
+
+    for (camera, projection, mut transform) in &mut cameras {
+        if !resized && !camera.is_changed() {
+            continue;
+        }
+        let Projection::Perspective(perspective) = projection else {
+            continue;
+        };
+        let direction = camera.view_direction();
+        let distance =
+            fit_distance(camera.bounds, direction, perspective.fov, aspect) / camera.zoom;
+        let target = camera.focus.extend(0.).xzy();
+        *transform = Transform::from_translation(target - direction * distance)
+            .looking_to(direction, Vec3::NEG_Z);
+    }
+}
+
+/// Distance from the center of `bounds` along `direction` at which all of `bounds` is in view.
+///
+/// `fov` is the vertical field of view in radians, `aspect` is width / height.
+pub fn fit_distance(bounds: Rect, direction: Vec3, fov: f32, aspect: f32) -> f32 {
+    let center = bounds.center().extend(0.).xzy();
+    let looking = Transform::IDENTITY.looking_to(direction, Vec3::NEG_Z);
+    let (right, up, forward) = (looking.right(), looking.up(), looking.forward());
+
+    let tan_v = (fov / 2.).tan() * (1. - FRAMING_MARGIN);
+    let tan_h = tan_v * aspect;
+
+    // For every corner, find how far back the camera must be for the corner to be on screen.
+    let mut distance: f32 = 0.;
+    for x in [bounds.min.x, bounds.max.x] {
+        for z in [bounds.min.y, bounds.max.y] {
+            for y in [0., CONTENT_HEIGHT] {
+                let offset = Vec3::new(x, y, z) - center;
+                let depth = offset.dot(*forward);
+                distance = distance
+                    .max(offset.dot(*right).abs() / tan_h - depth)
+                    .max(offset.dot(*up).abs() / tan_v - depth);
+            }
+        }
+    }
+    distance
+}
@@ -0,0 +1,399 @@
+//! Fluid fill visuals inside pipes
+
+use crate::AppState;
+use crate::assets::ModelAssets;
+use crate::options::Settings;
+use crate::pipes::{self, Fluids, Pipe, SlotId, Valve};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+pub struct FillPlugin;
+
+impl Plugin for FillPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_fill_mesh)
+            .add_systems(OnExit(AppState::LoadingAssets), show_fluid_through_pipes)
+            .add_systems(
+                Update,
+                (collect_fill_paths, spawn_fill, reroute_fill, update_fill).chain(),
+            );
+    }
+}
+
+/// Radius of the fluid stream, a little less than the inside of the pipe models.
+const FILL_RADIUS: f32 = 0.3;
+
+/// Opacity of the pipe models, low enough for the fluid inside to show through.
+const PIPE_ALPHA: f32 = 0.45;
+
+/// Most edges of a path between a side and the center of a pipe, each drawn by its own segment.
+const FILL_PIECES: usize = 4;
+
+/// Height of the bottom of the fluid column of a tank, on top of the tank chamber.
+const TANK_BASE: f32 = 0.6;
+
+/// Width of the fluid column showing the fill level of a tank, relative to the stream.
+const TANK_WIDTH: f32 = 1.6;
+
+/// Height of the fluid column of a full tank.
+const TANK_HEIGHT: f32 = 1.2;
+
+/// Polylines of the path nodes of `models/pipe.glb`, in the space of each node.
+///
+/// The paths are loose edges in `pipe.blend`, which the glTF export leaves out, so only the node
+/// transforms make it into the scenes.
+const PATHS: &[(&str, &[Vec3])] = &[
+    ("I Path", &[Vec3::NEG_X, Vec3::X]),
+    ("L Path", CURVE),
+    ("Cork Path", &[Vec3::ZERO, Vec3::X]),
+    ("T Path 1", &[Vec3::ZERO, Vec3::X]),
+    ("T Path 2", &[Vec3::ZERO, Vec3::X]),
+    ("T Path 3", &[Vec3::ZERO, Vec3::X]),
+    (
+        "X Path 1",
+        &[
+            Vec3::NEG_X,
+            Vec3::new(-0.333, -0.5, 0.),
+            Vec3::new(0.333, -0.5, 0.),
+            Vec3::X,
+        ],
+    ),
+    (
+        "X Path 2",
+        &[
+            Vec3::NEG_X,
+            Vec3::new(-0.333, 0.5, 0.),
+            Vec3::new(0.333, 0.5, 0.),
+            Vec3::X,
+        ],
+    ),
+    ("Catalyst Path 1", &[Vec3::ZERO, Vec3::X]),
+    ("Catalyst Path 2", &[Vec3::ZERO, Vec3::X]),
+    ("Down Path", CURVE),
+];
+
+/// Quarter circle of the curved paths.
+const CURVE: &[Vec3] = &[
+    Vec3::NEG_Z,
+    Vec3::new(0.076, 0., -0.617),
+    Vec3::new(0.293, 0., -0.293),
+    Vec3::new(0.617, 0., -0.076),
+    Vec3::X,
+];
+
+/// Fluid paths of a pipe model, in the space of the pipe.
+#[derive(Component, Debug)]
+struct FillPaths(Vec<Vec<Vec3>>);
+
+/// Unit length cylinder along Y, scaled to the filled length of each segment.
+#[derive(Resource, Debug)]
+struct FillMesh(Handle<Mesh>);
+
+/// Fluid running along one edge of the path between a pipe side and the pipe center.
+#[derive(Component, Debug)]
+struct FillSegment {
+    /// Index of the pipe channel the fluid runs in
+    channel: usize,
+    /// Local side of the pipe
+    side: SlotId,
+    /// Whether the fluid runs from the side to the center, rather than out from the center
+    inflow: bool,
+    /// Index of the path edge, in the order the fluid runs through them
+    piece: usize,
+}
+
+/// Column of fluid rising with the volume in a pipe container.
+#[derive(Component, Debug)]
+struct TankLevel {
+    /// Index of the container in the pipe
+    container: usize,
+}
+
+/// Number of pipe channels that already have their fill segments.
+#[derive(Component, Debug)]
+struct Filled(usize);
+
+fn initialize_fill_mesh(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(FillMesh(meshes.add(Cylinder::new(FILL_RADIUS, 1.0))));
+}
+
+/// Makes the pipe models see-through, so the fluid can be seen filling them.
+fn show_fluid_through_pipes(
+    models: Res<ModelAssets>,
+    gltfs: Res<Assets<Gltf>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Some(gltf) = gltfs.get(&models.pipe) else {
+        return;
+    };
+    for (name, material) in &gltf.named_materials {
+        if !name.starts_with("PipeBrass") {
+            continue;
+        }
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color.set_alpha(PIPE_ALPHA);
+            material.alpha_mode = AlphaMode::Blend;
+        }
+    }
+}
+
+/// Gathers the path nodes of newly spawned pipe models on their pipe, moved into its space.
+fn collect_fill_paths(
+    mut commands: Commands,
+    nodes: Query<(Entity, &Name), Added<Name>>,
+    transforms: Query<(&Transform, &ChildOf)>,
+    mut pipes: Query<Option<&mut FillPaths>, With<Pipe>>,
+) {
+    let mut found: HashMap<Entity, Vec<Vec<Vec3>>> = HashMap::new();
+    for (entity, name) in &nodes {
+        let Some((_, points)) = PATHS.iter().find(|(path, _)| *path == name.as_str()) else {
+            continue;
+        };
+        // Walk up through the scene root, and the upper span of bridges, to the pipe
+        let mut transform = Transform::IDENTITY;
+        let mut node = entity;
+        let pipe = loop {
+            let Ok((local, child_of)) = transforms.get(node) else {
+                break None;
+            };
+            transform = *local * transform;
+            node = child_of.parent();
+            if pipes.contains(node) {
+                break Some(node);
+            }
+        };
+        // Large pipes carry their model apart from the pipe parts
+        let Some(pipe) = pipe else {
+            continue;
+        };
+        found.entry(pipe).or_default().push(
+            points
+                .iter()
+                .map(|point| transform.transform_point(*point))
+                .collect(),
+        );
+    }
+
+    for (pipe, paths) in found {
+        match pipes.get_mut(pipe) {
+            Ok(Some(mut existing)) => existing.0.extend(paths),
+            _ => {
+                commands.entity(pipe).insert(FillPaths(paths));
+            }
+        }
+    }
+}
+
+/// Points the fluid runs through between a side of a pipe and its center, in flow order.
+///
+/// Follows the model path ending at the side, up to its middle when it runs on to another side.
+/// Pipes without a path there, like hex pipes, get a straight line.
+fn fill_path(paths: Option<&FillPaths>, side: SlotId, sides: SlotId, inflow: bool) -> Vec<Vec3> {
+    let direction = pipes::model_side_direction(side, sides);
+    let at_side = |point: &Vec3| point.xz().distance(direction.xz()) < 0.1;
+    let mut points = paths
+        .into_iter()
+        .flat_map(|paths| &paths.0)
+        .find_map(|path| {
+            if path.first().is_some_and(at_side) {
+                Some(path.clone())
+            } else if path.last().is_some_and(at_side) {
+                Some(path.iter().rev().copied().collect())
+            } else {
+                None
+            }
+        })
+        .map(|path| {
+            if path.last().is_some_and(|end| end.xz().length() > 0.5) {
+                first_half(&path)
+            } else {
+                path
+            }
+        })
+        .unwrap_or_else(|| vec![direction, Vec3::ZERO]);
+    if !inflow {
+        points.reverse();
+    }
+    points
+}
+
+/// Part of a polyline up to half its length.
+fn first_half(path: &[Vec3]) -> Vec<Vec3> {
+    let mut remaining = edge_lengths(path).sum::<f32>() / 2.0;
+    let mut half = vec![path[0]];
+    for pair in path.windows(2) {
+        let length = pair[0].distance(pair[1]);
+        if length >= remaining {
+            half.push(pair[0].lerp(pair[1], remaining / length));
+            break;
+        }
+        remaining -= length;
+        half.push(pair[1]);
+    }
+    half
+}
+
+fn edge_lengths(path: &[Vec3]) -> impl Iterator<Item = f32> + '_ {
+    path.windows(2).map(|pair| pair[0].distance(pair[1]))
+}
+
+/// Adds fill segments along the fluid path of pipe channels that just received fluid.
+fn spawn_fill(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe, Option<&Filled>), Changed<Pipe>>,
+    fluids: Res<Fluids>,
+    fill_mesh: Res<FillMesh>,
+    settings: Res<Settings>,
+) {
+    for (entity, pipe, filled) in &pipes {
+        let filled = filled.map_or(0, |filled| filled.0);
+        if pipe.channels.len() <= filled {
+            continue;
+        }
+
+        commands
+            .entity(entity)
+            .insert(Filled(pipe.channels.len()))
+            .with_children(|cmd| {
+                for (index, channel) in pipe.channels.iter().enumerate().skip(filled) {
+                    let Some(fluid) = fluids.get(&channel.fluid) else {
+                        continue;
+                    };
+                    let segments = pipe
+                        .inlet(channel)
+                        .map(|side| (side, true))
+                        .into_iter()
+                        .chain(
+                            pipe.exits(channel.entry)
+                                .into_iter()
+                                .map(|side| (side, false)),
+                        );
+                    for (side, inflow) in segments {
+                        for piece in 0..FILL_PIECES {
+                            cmd.spawn((
+                                FillSegment {
+                                    channel: index,
+                                    side,
+                                    inflow,
+                                    piece,
+                                },
+                                Mesh3d(fill_mesh.0.clone()),
+                                MeshMaterial3d(fluid.material(channel.temperature).clone()),
+                                Transform::default(),
+                                Visibility::Hidden,
+                            ));
+                        }
+                    }
+                    for container in pipe.containers_on(channel.entry) {
+                        cmd.spawn((
+                            TankLevel { container },
+                            Mesh3d(fill_mesh.0.clone()),
+                            MeshMaterial3d(fluid.material(channel.temperature).clone()),
+                            Transform::default(),
+                            Visibility::Hidden,
+                        ));
+                    }
+                    // Sources, sinks and springs already carry an icon
+                    if pipe.source.is_some() || pipe.sink.is_some() || pipe.spring.is_some() {
+                        continue;
+                    }
+                    // Icons of further channels sit towards their entry, clear of the first one
+                    let offset = pipe
+                        .inlet(channel)
+                        .filter(|_| index > 0)
+                        .map_or(Vec3::ZERO, |entry| {
+                            pipes::model_side_direction(entry, pipe.sides()) * 0.6
+                        });
+                    cmd.spawn(pipes::fluid_icon(fluid, &settings, offset));
+                }
+            });
+    }
+}
+
+/// Turns the outflow of diverters towards their new outlet once they switch, see
+/// [`Pipe::toggle_valve`].
+fn reroute_fill(
+    pipes: Query<(&Pipe, &Children), Changed<Pipe>>,
+    mut segments: Query<&mut FillSegment>,
+) {
+    for (pipe, children) in &pipes {
+        if !matches!(pipe.valve, Some(Valve::Diverter { .. })) {
+            continue;
+        }
+        for child in children {
+            let Ok(mut segment) = segments.get_mut(*child) else {
+                continue;
+            };
+            let Some(channel) = pipe.channels.get(segment.channel) else {
+                continue;
+            };
+            if !segment.inflow
+                && let [exit] = pipe.exits(channel.entry)[..]
+                && segment.side != exit
+            {
+                segment.side = exit;
+            }
+        }
+    }
+}
+
+/// Stretches the fill segments along the path of their channel to match its progress, and raises
+/// tank levels with the volume in the tank.
+///
+/// The fluid first runs from the entry side to the center, then out to all exits at once.
+fn update_fill(
+    pipes: Query<(&Pipe, Option<&FillPaths>, &Children), Or<(Changed<Pipe>, Changed<FillPaths>)>>,
+    mut segments: Query<(&FillSegment, &mut Transform, &mut Visibility)>,
+    mut levels: Query<(&TankLevel, &mut Transform, &mut Visibility), Without<FillSegment>>,
+) {
+    for (pipe, paths, children) in &pipes {
+        for child in children {
+            if let Ok((level, mut transform, mut visibility)) = levels.get_mut(*child)
+                && let Some(container) = pipe.containers.get(level.container)
+            {
+                let fill = (container.volume / container.capacity).clamp(0.0, 1.0);
+                *transform = Transform::from_xyz(0., TANK_BASE + fill * TANK_HEIGHT / 2., 0.)
+                    .with_scale(Vec3::new(TANK_WIDTH, fill * TANK_HEIGHT, TANK_WIDTH));
+                visibility.set_if_neq(if fill > 0.0 {
+                    Visibility::Inherited
+                } else {
+                    Visibility::Hidden
+                });
+                continue;
+            }
+
+            let Ok((segment, mut transform, mut visibility)) = segments.get_mut(*child) else {
+                continue;
+            };
+            let Some(channel) = pipe.channels.get(segment.channel) else {
+                continue;
+            };
+            let inflow_share = pipe.inflow_share(channel);
+            let fill = if segment.inflow {
+                channel.progress / inflow_share
+            } else {
+                (channel.progress - inflow_share) / (1.0 - inflow_share)
+            }
+            .clamp(0.0, 1.0);
+
+            let path = fill_path(paths, segment.side, pipe.sides(), segment.inflow);
+            let lengths: Vec<f32> = edge_lengths(&path).collect();
+            let Some(&length) = lengths.get(segment.piece) else {
+                visibility.set_if_neq(Visibility::Hidden);
+                continue;
+            };
+            let before: f32 = lengths[..segment.piece].iter().sum();
+            let covered = (fill * lengths.iter().sum::<f32>() - before).clamp(0.0, length);
+            let start = path[segment.piece];
+            let direction = (path[segment.piece + 1] - start).normalize();
+            *transform = Transform::from_translation(start + direction * covered / 2.0)
+                .with_rotation(Quat::from_rotation_arc(Vec3::Y, direction))
+                .with_scale(Vec3::new(1.0, covered, 1.0));
+            visibility.set_if_neq(if covered > 0.0 {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            });
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Fluid flow simulation
+
+use crate::game::{FlowTime, PipeGameState};
+use crate::level::{CurrentLevel, GridCell, LeakRule, Level, LevelGrid, SinkRequirements};
+use crate::pipes::{
+    self, AMBIENT_TEMPERATURE, Channel, FluidId, Fluids, INTERNAL_SOURCE, LargePipeRoot, PORTAL,
+    Pipe, PipePart, Slot, SlotId,
+};
+use crate::valves::ValveTimer;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+pub struct FlowPlugin;
+
+impl Plugin for FlowPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(PipeGameState::Flowing), open_sources)
+            .add_systems(
+                Update,
+                (open_delayed_sources, advance_flow)
+                    .chain()
+                    .run_if(in_state(PipeGameState::Flowing)),
+            );
+    }
+}
+
+/// Seconds the flow may stand still in front of closed gates before the level fails, unless one
+/// of them is going to open on its own.
+const GATE_PATIENCE: f32 = 10.0;
+
+/// Fluid spilled out of the network during the flow, one entry for every leaking side.
+#[derive(Resource, Debug, Default)]
+pub struct Spills(pub Vec<Spill>);
+
+impl Spills {
+    /// Total volume spilled, in pipe fills.
+    pub fn volume(&self) -> f32 {
+        self.0.iter().map(|spill| spill.volume).sum()
+    }
+}
+
+/// Time left until a source opens, counted from the start of the flow.
+///
+/// Removed once the source has opened.
+#[derive(Component, Debug)]
+pub struct SourceDelay(pub Timer);
+
+/// Progress of a sink towards its requirements.
+///
+/// Full sinks take in the fluid that keeps being pushed into them, which counts towards their
+/// volume and hold time.
+#[derive(Component, Debug)]
+pub struct SinkGoal {
+    pub requirements: SinkRequirements,
+    /// Volume taken in since the sink filled up, in pipe fills
+    pub received: f32,
+    /// Seconds the sink has stayed full with its fluid coming in, without a break
+    pub held: f32,
+    /// Whether all requirements were met, which stays so when the fluid stops coming in later
+    pub met: bool,
+}
+
+impl SinkGoal {
+    pub fn new(requirements: SinkRequirements) -> Self {
+        SinkGoal {
+            requirements,
+            received: 0.0,
+            held: 0.0,
+            met: false,
+        }
+    }
+
+    /// Volume the sink has taken in, including the fluid filling it, in pipe fills.
+    pub fn delivered(&self, sink: &Pipe) -> f32 {
+        sink.channels
+            .first()
+            .map_or(0.0, |channel| channel.progress)
+            + self.received
+    }
+
+    /// Share of the required volume taken in, from 0 to 1.
+    pub fn volume_progress(&self, sink: &Pipe) -> f32 {
+        (self.delivered(sink) / self.requirements.volume).clamp(0.0, 1.0)
+    }
+
+    /// Share of the hold time the sink has stayed full, from 0 to 1; 1 without a hold time.
+    pub fn hold_progress(&self) -> f32 {
+        self.requirements
+            .hold
+            .map_or(1.0, |hold| (self.held / hold).clamp(0.0, 1.0))
+    }
+
+    fn is_satisfied(&self, sink: &Pipe) -> bool {
+        sink.is_full() && self.volume_progress(sink) >= 1.0 && self.hold_progress() >= 1.0
+    }
+}
+
+/// Fluid pushed out of a side without a neighbour taking it in, which spills there at the rate it
+/// fills its channel.
+#[derive(Debug)]
+pub struct Spill {
+    /// Cell of the pipe the fluid spills out of
+    pub cell: IVec2,
+    /// World side of the cell the fluid spills over
+    pub side: SlotId,
+    pub fluid: FluidId,
+    /// Volume spilled so far, in pipe fills
+    pub volume: f32,
+}
+
+/// Starts the fluid in sources and springs that open right away.
+fn open_sources(
+    mut commands: Commands,
+    mut pipes: Query<&mut Pipe, Without<SourceDelay>>,
+    fluids: Res<Fluids>,
+) {
+    commands.insert_resource(Spills::default());
+    for mut pipe in &mut pipes {
+        open_source(&mut pipe, &fluids);
+    }
+}
+
+/// Starts the fluid in delayed sources once their time has come.
+fn open_delayed_sources(
+    mut commands: Commands,
+    mut pipes: Query<(Entity, &mut Pipe, &mut SourceDelay, &GridCell)>,
+    fluids: Res<Fluids>,
+    time: Res<Time>,
+) {
+    for (entity, mut pipe, mut delay, cell) in &mut pipes {
+        if delay.0.tick(time.delta()).finished() {
+            info!("Delayed source at {} opened", cell.0);
+            open_source(&mut pipe, &fluids);
+            commands.entity(entity).remove::<SourceDelay>();
+        }
+    }
+}
+
+/// Starts the fluid in a source or spring, at the speed its viscosity allows.
+fn open_source(pipe: &mut Pipe, fluids: &Fluids) {
+    let (fluid, entry) = if let Some(fluid) = pipe.source.clone() {
+        (fluid, None)
+    } else if let Some(spring) = &pipe.spring {
+        (spring.fluid.clone(), Some(INTERNAL_SOURCE))
+    } else {
+        return;
+    };
+    let (temperature, viscosity) = fluids
+        .get(&fluid)
+        .map_or((AMBIENT_TEMPERATURE, 1.0), |fluid| {
+            (fluid.temperature, fluid.viscosity)
+        });
+    let speed = pipe.pump / viscosity;
+    pipe.channels = vec![Channel {
+        fluid,
+        entry,
+        progress: 0.0,
+        temperature,
+        speed,
+    }];
+}
+
+/// Fills the channels of pipes that have fluid in them, and pushes the fluid of full channels
+/// on into the neighbours.
+///
+/// Only channels still fed with fluid fill and push, see [`supplied_channels`]. How the fluid
+/// fills and moves on is up to the pipes it runs into, see [`Channel`], [`Pipe::fill_rate`],
+/// [`Pipe::pour`], [`Pipe::passed_temperature`], [`Pipe::mix_in`], [`Pipe::teleports`],
+/// [`exchange_temperature`], [`pipes::Spring`], [`pipes::Drain`] and [`Spill`].
+///
+/// The level is won once every sink is done, see [`SinkGoal`]. It fails when fluid pushes
+/// backwards through a check valve, a sink gets the wrong fluid or fluid outside its temperature
+/// range, a sink misses its deadline, fluid spills more than the [`LeakRule`] allows, or the flow
+/// stops before all sinks are done, see [`GATE_PATIENCE`].
+fn advance_flow(
+    mut pipes: Query<(Entity, &mut Pipe, &GridCell)>,
+    parts: Query<&PipePart>,
+    roots: Query<&LargePipeRoot>,
+    grid: Res<LevelGrid>,
+    mut spills: ResMut<Spills>,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+    delayed: Query<(), With<SourceDelay>>,
+    timed_valves: Query<(), With<ValveTimer>>,
+    mut goals: Query<(Entity, &mut SinkGoal)>,
+    flow_time: Res<FlowTime>,
+    time: Res<Time>,
+    mut stalled: Local<f32>,
+    mut game_state: ResMut<NextState<PipeGameState>>,
+) {
+    // Full channels keep pushing, so fluid held back by a closed gate moves on once it opens
+    let supplied = supplied_channels(&pipes, &grid);
+    // Volume the springs have left, which everything they feed takes its fluid from
+    let mut springs: HashMap<Entity, f32> = pipes
+        .iter()
+        .filter_map(|(entity, pipe, _)| Some((entity, pipe.spring.as_ref()?.remaining())))
+        .collect();
+    let mut draw = |channel: (Entity, usize), volume: f32| match supplied
+        .get(&channel)
+        .copied()
+        .flatten()
+        .and_then(|spring| springs.get_mut(&spring))
+    {
+        Some(left) => {
+            let volume = volume.min(*left);
+            *left -= volume;
+            volume
+        }
+        None => volume,
+    };
+
+    let mut moving = false;
+    let mut full = Vec::new();
+    for (entity, mut pipe, _) in &mut pipes {
+        let filling: Vec<_> = (0..pipe.channels.len())
+            .filter(|index| {
+                supplied.contains_key(&(entity, *index))
+                    && !pipe.is_channel_full(&pipe.channels[*index])
+            })
+            .collect();
+        for index in filling {
+            let channel = &pipe.channels[index];
+            let volume = (pipe.fill_rate(channel) * time.delta_secs())
+                .min(pipe.volume_to_fill(channel.entry, channel.progress));
+            // Mixing chambers stand full until the other fluids are in
+            moving |= volume > 0.0;
+            pipe.pour(index, draw((entity, index), volume));
+        }
+        for (index, channel) in pipe.channels.iter().enumerate() {
+            if supplied.contains_key(&(entity, index)) && pipe.is_channel_full(channel) {
+                full.push((entity, index));
+            }
+        }
+    }
+
+    let mut waiting = false;
+    let mut waiting_on_timer = false;
+    let mut leaks = Vec::new();
+    let mut feeds = Vec::new();
+    let mut drains = Vec::new();
+    for (entity, index) in full {
+        let Ok((_, pipe, cell)) = pipes.get(entity) else {
+            continue;
+        };
+        let channel = &pipe.channels[index];
+        if let Some(sink) = &pipe.sink {
+            if &channel.fluid != sink {
+                info!("Sink at {} got the wrong fluid", cell.0);
+                game_state.set(PipeGameState::LevelFailed);
+                return;
+            }
+            if let Some(range) = pipe
+                .sink_temperature
+                .filter(|range| !range.contains(channel.temperature))
+            {
+                info!(
+                    "Sink at {} got fluid at {:.0}°C, wanted {:.0}-{:.0}°C",
+                    cell.0, channel.temperature, range.min, range.max
+                );
+                game_state.set(PipeGameState::LevelFailed);
+                return;
+            }
+            continue;
+        }
+
+        if let Some(drain) = &pipe.drain
+            && !drain.is_full()
+            && pipe.drains(channel.entry)
+        {
+            drains.push((
+                entity,
+                draw((entity, index), drain.rate * time.delta_secs()),
+            ));
+        }
+
+        let fluid = channel.fluid.clone();
+        let temperature = channel.temperature;
+        let speed = channel.speed;
+        let spilled = draw((entity, index), pipe.fill_rate(channel) * time.delta_secs());
+        let portal = pipe.teleports(channel.entry);
+        let cell = cell.0;
+        let exits: Vec<_> = pipe
+            .exits(channel.entry)
+            .into_iter()
+            .map(|local| pipe.world_side(local))
+            .collect();
+
+        for side in exits {
+            let facing = pipes::opposite_side(side, grid.shape.sides());
+            let target = grid.neighbour(cell, side);
+            let exchange = grid
+                .get(target)
+                .and_then(|neighbour| exchange_temperature(neighbour, &pipes, &parts, &roots));
+            let neighbour = grid
+                .get(target)
+                .and_then(|neighbour| pipes.get_mut(neighbour).ok());
+            let Some((next_entity, mut next, _)) =
+                neighbour.filter(|(_, next, _)| next.slot(facing).accepts_input())
+            else {
+                if grid
+                    .get(target)
+                    .and_then(|neighbour| pipes.get(neighbour).ok())
+                    .is_some_and(|(_, next, _)| matches!(next.slot(facing), Slot::Output))
+                {
+                    info!("Backflow from {} on side {}", cell, side);
+                    game_state.set(PipeGameState::LevelFailed);
+                    return;
+                }
+                leaks.push((cell, side, fluid.clone(), spilled));
+                continue;
+            };
+
+            let entry = next.local_side(facing);
+            if next.is_closed() {
+                waiting = true;
+                waiting_on_timer |= timed_valves.contains(next_entity);
+            } else if next.mixing_channel(entry).is_some() {
+                moving |= next.mix_in(entry, &fluid, temperature);
+            } else if next.channel_at(entry).is_none() {
+                moving = true;
+                let temperature = match exchange {
+                    Some(other) => (next.passed_temperature(temperature) + other) / 2.0,
+                    None => next.passed_temperature(temperature),
+                };
+                let speed = speed * next.pump;
+                next.channels.push(Channel {
+                    fluid: fluid.clone(),
+                    entry: Some(entry),
+                    progress: 0.0,
+                    temperature,
+                    speed,
+                });
+            } else if next.sink.as_ref() == Some(&fluid)
+                && next.is_full()
+                && next
+                    .sink_temperature
+                    .is_none_or(|range| range.contains(next.passed_temperature(temperature)))
+            {
+                feeds.push((next_entity, spilled));
+            }
+        }
+
+        if let Some(partner) = portal
+            && let Ok((_, mut next, _)) = pipes.get_mut(partner)
+            && !next.is_portal_in_use()
+        {
+            moving = true;
+            let temperature = next.passed_temperature(temperature);
+            let speed = speed * next.pump;
+            next.channels.push(Channel {
+                fluid,
+                entry: Some(PORTAL),
+                progress: 0.0,
+                temperature,
+                speed,
+            });
+        }
+    }
+
+    // Only touch the pipes whose spring or drain changed, to keep change detection quiet
+    for (entity, left) in springs {
+        if let Ok((_, mut pipe, _)) = pipes.get_mut(entity)
+            && pipe
+                .spring
+                .as_ref()
+                .is_some_and(|spring| spring.remaining() > left)
+            && let Some(spring) = &mut pipe.spring
+        {
+            spring.produced = spring.capacity - left;
+        }
+    }
+    moving |= !drains.is_empty();
+    for (entity, volume) in drains {
+        if let Ok((_, mut pipe, cell)) = pipes.get_mut(entity)
+            && let Some(drain) = &mut pipe.drain
+        {
+            drain.drained = (drain.drained + volume).min(drain.capacity);
+            if drain.is_full() {
+                info!("Drain at {} is full", cell.0);
+            }
+        }
+    }
+
+    let mut new_leak = false;
+    for (cell, side, fluid, volume) in leaks {
+        match spills
+            .0
+            .iter_mut()
+            .find(|spill| spill.cell == cell && spill.side == side)
+        {
+            Some(spill) => spill.volume += volume,
+            None => {
+                info!("Fluid leaked out of {} on side {}", cell, side);
+                new_leak = true;
+                spills.0.push(Spill {
+                    cell,
+                    side,
+                    fluid,
+                    volume,
+                });
+            }
+        }
+    }
+    let leak_rule = levels
+        .get(&current_level.0)
+        .map(|level| level.leak_rule)
+        .unwrap_or_default();
+    match leak_rule {
+        LeakRule::Fail if new_leak => {
+            game_state.set(PipeGameState::LevelFailed);
+            return;
+        }
+        LeakRule::Threshold(limit) if spills.volume() > limit => {
+            info!("Spilled more than {:.1} of fluid", limit);
+            game_state.set(PipeGameState::LevelFailed);
+            return;
+        }
+        _ => {}
+    }
+
+    let mut feeding = false;
+    for (entity, mut goal) in &mut goals {
+        if goal.met {
+            continue;
+        }
+        let Ok((_, sink, cell)) = pipes.get(entity) else {
+            continue;
+        };
+        let fed: f32 = feeds
+            .iter()
+            .filter(|(fed, _)| *fed == entity)
+            .map(|(_, volume)| volume)
+            .sum();
+        if fed > 0.0 {
+            feeding = true;
+            goal.received += fed;
+            goal.held += time.delta_secs();
+        } else if goal.held > 0.0 {
+            goal.held = 0.0;
+        }
+        if goal.is_satisfied(sink) {
+            info!("Sink at {} is done", cell.0);
+            goal.met = true;
+        } else if let Some(deadline) = goal
+            .requirements
+            .deadline
+            .filter(|deadline| flow_time.0.elapsed_secs() > *deadline)
+        {
+            info!("Sink at {} missed its deadline of {:.1}s", cell.0, deadline);
+            game_state.set(PipeGameState::LevelFailed);
+            return;
+        }
+    }
+
+    let mut sinks = pipes
+        .iter()
+        .filter(|(_, pipe, _)| pipe.sink.is_some())
+        .peekable();
+    let has_sinks = sinks.peek().is_some();
+    if has_sinks
+        && sinks.all(|(entity, pipe, _)| {
+            pipe.is_full()
+                && pipe
+                    .channels
+                    .iter()
+                    .all(|channel| pipe.sink.as_ref() == Some(&channel.fluid))
+                && goals.get(entity).is_ok_and(|(_, goal)| goal.met)
+        })
+    {
+        info!("All sinks are done");
+        game_state.set(PipeGameState::LevelWon);
+    } else if !moving && !feeding && !waiting_on_timer && delayed.is_empty() {
+        *stalled += time.delta_secs();
+        if !waiting || *stalled > GATE_PATIENCE {
+            info!("Flow stopped before all sinks were filled");
+            game_state.set(PipeGameState::LevelFailed);
+        }
+    } else {
+        *stalled = 0.0;
+    }
+}
+
+/// Channels that fluid still runs into, from a source or a spring that hasn't run dry, through
+/// full channels, open gates and portals, with the spring they get their fluid from.
+///
+/// Channels cut off from their supply, like those past a gate that closed after the fluid went
+/// through, hold their fluid instead of filling up and pushing it on.
+fn supplied_channels(
+    pipes: &Query<(Entity, &mut Pipe, &GridCell)>,
+    grid: &LevelGrid,
+) -> HashMap<(Entity, usize), Option<Entity>> {
+    let mut open = Vec::new();
+    for (entity, pipe, _) in pipes {
+        for (index, channel) in pipe.channels.iter().enumerate() {
+            match channel.entry {
+                None => open.push((entity, index, None)),
+                Some(INTERNAL_SOURCE)
+                    if pipe.spring.as_ref().is_some_and(|spring| !spring.is_dry()) =>
+                {
+                    open.push((entity, index, Some(entity)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut supplied = HashMap::new();
+    while let Some((entity, index, spring)) = open.pop() {
+        if supplied.insert((entity, index), spring).is_some() {
+            continue;
+        }
+        let Ok((_, pipe, cell)) = pipes.get(entity) else {
+            continue;
+        };
+        let channel = &pipe.channels[index];
+        if !pipe.is_channel_full(channel) {
+            continue;
+        }
+        // The channels the fluid runs on into are the ones that came in from this side
+        for local in pipe.exits(channel.entry) {
+            let side = pipe.world_side(local);
+            let facing = pipes::opposite_side(side, grid.shape.sides());
+            let Some((next_entity, next, _)) = grid
+                .get(grid.neighbour(cell.0, side))
+                .and_then(|neighbour| pipes.get(neighbour).ok())
+                .filter(|(_, next, _)| !next.is_closed())
+            else {
+                continue;
+            };
+            let entry = Some(next.local_side(facing));
+            if let Some(next_index) = next.channels.iter().position(|next| next.entry == entry) {
+                open.push((next_entity, next_index, spring));
+            }
+        }
+        if let Some((partner, next, _)) = pipe
+            .teleports(channel.entry)
+            .and_then(|partner| pipes.get(partner).ok())
+            && let Some(next_index) = next
+                .channels
+                .iter()
+                .position(|next| next.entry == Some(PORTAL))
+        {
+            open.push((partner, next_index, spring));
+        }
+    }
+    supplied
+}
+
+/// Mean temperature of the fluid in the other parts of the heat exchanging large pipe that
+/// `entity` is a part of, if there is any.
+fn exchange_temperature(
+    entity: Entity,
+    pipes: &Query<(Entity, &mut Pipe, &GridCell)>,
+    parts: &Query<&PipePart>,
+    roots: &Query<&LargePipeRoot>,
+) -> Option<f32> {
+    let root = roots
+        .get(parts.get(entity).ok()?.0)
+        .ok()
+        .filter(|root| root.exchanges_heat)?;
+    let temperatures: Vec<f32> = root
+        .parts
+        .iter()
+        .filter(|(_, part)| *part != entity)
+        .filter_map(|(_, part)| pipes.get(*part).ok())
+        .flat_map(|(_, pipe, _)| pipe.channels.iter().map(|channel| channel.temperature))
+        .collect();
+    (!temperatures.is_empty()).then(|| temperatures.iter().sum::<f32>() / temperatures.len() as f32)
+}
+
+/// Estimates the seconds until each pipe the fluid is going to reach is full.
+///
+/// Follows the fluid from the channels it is in now through the current layout of the network,
+/// at the rates it would fill every channel on the way. Pipes behind closed gates or leaks don't
+/// get an estimate, and dry springs and drains aren't accounted for.
+pub fn estimate_fill_times(
+    pipes: &Query<(Entity, &Pipe, &GridCell)>,
+    grid: &LevelGrid,
+) -> HashMap<Entity, f32> {
+    // Channels with the time they are full at, and the speed of the fluid in them
+    let mut open: Vec<(f32, Entity, Option<SlotId>, f32)> = Vec::new();
+    for (entity, pipe, _) in pipes {
+        for channel in &pipe.channels {
+            let time = if pipe.is_channel_full(channel) {
+                0.0
+            } else {
+                pipe.volume_to_fill(channel.entry, channel.progress) / pipe.fill_rate(channel)
+            };
+            open.push((time, entity, channel.entry, channel.speed));
+        }
+    }
+
+    let mut times = HashMap::new();
+    let mut visited = Vec::new();
+    // Always take the channel filling up first, so every pipe gets the earliest time
+    while let Some(index) = (0..open.len()).min_by(|a, b| open[*a].0.total_cmp(&open[*b].0)) {
+        let (time, entity, entry, speed) = open.swap_remove(index);
+        if visited.contains(&(entity, entry)) {
+            continue;
+        }
+        visited.push((entity, entry));
+        times.entry(entity).or_insert(time);
+
+        let Ok((_, pipe, cell)) = pipes.get(entity) else {
+            continue;
+        };
+        for local in pipe.exits(entry) {
+            let side = pipe.world_side(local);
+            let facing = pipes::opposite_side(side, grid.shape.sides());
+            let Some((neighbour, next, _)) = grid
+                .get(grid.neighbour(cell.0, side))
+                .and_then(|neighbour| pipes.get(neighbour).ok())
+                .filter(|(_, next, _)| next.slot(facing).accepts_input() && !next.is_closed())
+            else {
+                continue;
+            };
+            let entry = next.local_side(facing);
+            // Channels that already have fluid are counted from their own progress
+            if next.channel_at(entry).is_some() {
+                continue;
+            }
+            let speed = speed * next.pump;
+            let rate = next.progress_rate * speed;
+            let time = time + next.volume_to_fill(Some(entry), 0.0) / rate;
+            open.push((time, neighbour, Some(entry), speed));
+        }
+        if let Some((partner, next, _)) = pipe
+            .teleports(entry)
+            .and_then(|partner| pipes.get(partner).ok())
+            .filter(|(_, next, _)| !next.is_portal_in_use())
+        {
+            let speed = speed * next.pump;
+            let rate = next.progress_rate * speed;
+            let time = time + next.volume_to_fill(Some(PORTAL), 0.0) / rate;
+            open.push((time, partner, Some(PORTAL), speed));
+        }
+    }
+    times
+}
@@ -1,7 +1,16 @@
 //! Game logic
 use crate::AppState;
-use crate::level::{CurrentLevel, Level};
+use crate::audio::{PlaySfx, Sfx};
+use crate::camera::LevelCamera;
+use crate::level::{CurrentLevel, GameMode, GridCell, Level, LevelGrid, LoadNextLevel};
+use crate::menu::{self, Disabled};
+use crate::pipes::{self, LargePipeRoot, Pipe};
+use crate::scoring::{self, LevelScore};
+use crate::swap::Dragged;
+use crate::theme::{TextRole, UiTheme};
+use bevy::input_focus::tab_navigation::TabGroup;
 use bevy::prelude::*;
+use bevy::time::Stopwatch;
 use serde::Deserialize;
 
 pub struct PipeGamePlugin;
@@ -11,9 +20,23 @@ impl Plugin for PipeGamePlugin {
         app.add_sub_state::<PipeGameState>()
             .add_systems(OnEnter(AppState::InGame), setup_game_scene)
             .add_systems(OnExit(AppState::InGame), cleanup)
+            .add_systems(OnEnter(PipeGameState::Prepare), start_prepare_timer)
+            .add_systems(OnEnter(PipeGameState::Flowing), start_flow_time)
+            .add_systems(
+                OnEnter(PipeGameState::LevelWon),
+                setup_results.after(scoring::score_level),
+            )
+            .add_systems(OnEnter(PipeGameState::LevelFailed), setup_results)
             .add_systems(
                 Update,
-                (warmup_timer).run_if(in_state(PipeGameState::Warmup)),
+                (
+                    (warmup_timer).run_if(in_state(PipeGameState::Warmup)),
+                    (prepare_timer, start_flow_early).run_if(in_state(PipeGameState::Prepare)),
+                    (flow_time).run_if(in_state(PipeGameState::Flowing)),
+                    (menu::update_button_color, results_action).run_if(
+                        in_state(PipeGameState::LevelWon).or(in_state(PipeGameState::LevelFailed)),
+                    ),
+                ),
             );
     }
 }
@@ -31,7 +54,7 @@ pub enum PipeGameState {
 
 /// Marker for game state entities for automatic cleanup.
 #[derive(Component, Debug)]
-struct GameEntity;
+pub struct GameEntity;
 
 fn setup_game_scene(
     mut commands: Commands,
@@ -39,13 +62,19 @@ fn setup_game_scene(
     current_level: Res<CurrentLevel>,
 ) {
     info!("Setting up game scene");
+    let bounds = levels
+        .get(&current_level.0)
+        .map(|level| level.data.bounds())
+        .unwrap_or(Rect::from_center_size(Vec2::ZERO, Vec2::splat(10.)));
     commands.spawn((
         Camera3d::default(),
         Camera {
             order: 0,
             ..default()
         },
+        // Placeholder until the camera is fitted to the level
         Transform::from_xyz(0., 15., 5.).looking_at(Vec3::ZERO, Vec3::Y),
+        LevelCamera::new(bounds),
         GameEntity,
     ));
 
@@ -57,6 +86,7 @@ fn setup_game_scene(
 
     // set warmup timer (grace period before becomes interactive)
     commands.insert_resource(WarmupTimer(Timer::from_seconds(1.0, TimerMode::Once)));
+    commands.insert_resource(Moves::default());
 }
 
 fn cleanup(mut commands: Commands, entities: Query<Entity, With<GameEntity>>) {
@@ -65,6 +95,11 @@ fn cleanup(mut commands: Commands, entities: Query<Entity, With<GameEntity>>) {
     }
 
     commands.remove_resource::<WarmupTimer>();
+    commands.remove_resource::<PrepareTimer>();
+    commands.remove_resource::<FlowTime>();
+    commands.remove_resource::<LevelGrid>();
+    commands.remove_resource::<Moves>();
+    commands.remove_resource::<LevelScore>();
 }
 
 #[derive(Resource, Debug)]
@@ -82,3 +117,279 @@ fn warmup_timer(
         game_state.set(PipeGameState::Prepare);
     }
 }
+
+/// Time left for the player to arrange pipes before the sources open.
+#[derive(Resource, Debug)]
+pub struct PrepareTimer(pub Timer);
+
+fn start_prepare_timer(
+    mut commands: Commands,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+) {
+    let prepare_time = levels
+        .get(&current_level.0)
+        .map(|level| level.prepare_time)
+        .unwrap_or_default();
+    commands.insert_resource(PrepareTimer(Timer::from_seconds(
+        prepare_time,
+        TimerMode::Once,
+    )));
+}
+
+fn prepare_timer(
+    mut timer: ResMut<PrepareTimer>,
+    mut game_state: ResMut<NextState<PipeGameState>>,
+    time: Res<Time>,
+) {
+    timer.0.tick(time.delta());
+
+    if timer.0.finished() {
+        info!("Prepare is finished, starting flow");
+        game_state.set(PipeGameState::Flowing);
+    }
+}
+
+/// Opens the sources before the prepare time is up; the time left counts towards the score.
+fn start_flow_early(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut game_state: ResMut<NextState<PipeGameState>>,
+) {
+    if keys.just_pressed(KeyCode::Space)
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.just_pressed(GamepadButton::West))
+    {
+        info!("Starting flow early");
+        game_state.set(PipeGameState::Flowing);
+    }
+}
+
+/// Time since the sources opened.
+#[derive(Resource, Debug, Default)]
+pub struct FlowTime(pub Stopwatch);
+
+fn start_flow_time(mut commands: Commands) {
+    commands.insert_resource(FlowTime::default());
+}
+
+fn flow_time(mut flow_time: ResMut<FlowTime>, time: Res<Time>) {
+    flow_time.0.tick(time.delta());
+}
+
+/// Number of pipe rotations made in the current level.
+#[derive(Resource, Debug, Default)]
+pub struct Moves(pub u32);
+
+/// Whether the player can work on the pipes right now, which is while preparing or flowing.
+pub fn can_interact(game_state: Option<&State<PipeGameState>>) -> bool {
+    matches!(
+        game_state.map(State::get),
+        Some(PipeGameState::Prepare | PipeGameState::Flowing)
+    )
+}
+
+/// Rotates a clicked pipe clockwise by one side, a quarter turn on square grids.
+///
+/// Only unlocked pipes without fluid in them can be turned, while preparing or flowing.
+pub fn rotate_pipe(
+    trigger: Trigger<Pointer<Click>>,
+    mut pipes: Query<(&mut Pipe, &mut Transform), Without<Dragged>>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    moves: Option<ResMut<Moves>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    // Sliding puzzles are solved by moving the pipes only
+    if current_level
+        .and_then(|current_level| levels.get(&current_level.0))
+        .is_some_and(|level| level.mode == GameMode::Sliding)
+    {
+        return;
+    }
+    if !can_interact(game_state.as_deref()) {
+        return;
+    }
+    let Ok((mut pipe, mut transform)) = pipes.get_mut(trigger.target()) else {
+        return;
+    };
+    // Valves are operated instead of turned
+    if pipe.locked || pipe.has_fluid() || pipe.valve.is_some() {
+        return;
+    }
+
+    pipe.rotation = (pipe.rotation + 1) % pipe.sides();
+    transform.rotation = pipes::rotation_quat(pipe.rotation, pipe.sides());
+    if let Some(mut moves) = moves {
+        moves.0 += 1;
+    }
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
+
+/// Rotates a clicked large pipe a quarter turn clockwise, with all of its parts.
+///
+/// The piece keeps its top-left cell, and only turns if the cells it turns onto are free. Like
+/// single pipes, it stays put while locked or once any part has fluid in it.
+pub fn rotate_large_pipe(
+    trigger: Trigger<Pointer<Click>>,
+    mut roots: Query<(&mut LargePipeRoot, &mut Transform), Without<Pipe>>,
+    mut parts: Query<(&mut Pipe, &mut GridCell, &mut Transform)>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    grid: Option<ResMut<LevelGrid>>,
+    moves: Option<ResMut<Moves>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    let Some(level) = current_level.and_then(|current_level| levels.get(&current_level.0)) else {
+        return;
+    };
+    if level.mode == GameMode::Sliding || !can_interact(game_state.as_deref()) {
+        return;
+    }
+    let Some(mut grid) = grid else {
+        return;
+    };
+    let Ok((mut root, mut transform)) = roots.get_mut(trigger.target()) else {
+        return;
+    };
+    if root.locked
+        || root
+            .parts
+            .iter()
+            .any(|(_, part)| parts.get(*part).is_ok_and(|(pipe, _, _)| pipe.has_fluid()))
+    {
+        return;
+    }
+
+    let rotation = (root.rotation + 1) % pipes::SIDES;
+    let offsets: Vec<_> = root.parts.iter().map(|(offset, _)| *offset).collect();
+    let cells: Vec<_> = pipes::turn_footprint(&offsets, rotation)
+        .into_iter()
+        .map(|offset| root.origin + offset)
+        .collect();
+    let own = |entity: Entity| root.parts.iter().any(|(_, part)| *part == entity);
+    if cells
+        .iter()
+        .any(|cell| !grid.contains(*cell) || grid.get(*cell).is_some_and(|entity| !own(entity)))
+    {
+        sfx.write(PlaySfx(Sfx::Invalid));
+        return;
+    }
+
+    for (_, part) in &root.parts {
+        if let Ok((_, cell, _)) = parts.get(*part) {
+            grid.clear(cell.0);
+        }
+    }
+    for ((_, part), cell) in root.parts.iter().zip(&cells) {
+        let Ok((mut pipe, mut grid_cell, mut part_transform)) = parts.get_mut(*part) else {
+            continue;
+        };
+        let center = level.data.cell_center(*cell);
+        pipe.rotation = rotation;
+        grid_cell.0 = *cell;
+        *part_transform = Transform::from_xyz(center.x, 0., center.y)
+            .with_rotation(pipes::rotation_quat(rotation, pipes::SIDES));
+        grid.set(*cell, *part);
+    }
+    let center = cells
+        .iter()
+        .map(|cell| level.data.cell_center(*cell))
+        .sum::<Vec2>()
+        / cells.len() as f32;
+    transform.translation = Vec3::new(center.x, 0., center.y);
+    transform.rotation = pipes::rotation_quat(rotation, pipes::SIDES);
+    root.rotation = rotation;
+
+    if let Some(mut moves) = moves {
+        moves.0 += 1;
+    }
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
+
+#[derive(Component, Debug)]
+enum ResultsAction {
+    Retry,
+    LevelSelect,
+}
+
+fn setup_results(
+    mut commands: Commands,
+    theme: UiTheme,
+    game_state: Res<State<PipeGameState>>,
+    score: Option<Res<LevelScore>>,
+) {
+    let title = match game_state.get() {
+        PipeGameState::LevelWon => "Level complete!",
+        _ => "Level failed",
+    };
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(10.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.5)),
+            GameEntity,
+            TabGroup::default(),
+        ))
+        .with_children(|cmd| {
+            cmd.spawn((theme.text(title, TextRole::Title), TextShadow::default()));
+            if let Some(score) = score.filter(|_| *game_state.get() == PipeGameState::LevelWon) {
+                cmd.spawn(scoring::star_row(score.stars, 48.0));
+                cmd.spawn(theme.text(&format!("Score: {}", score.score), TextRole::Body));
+                if score.bonuses > 0 {
+                    cmd.spawn(theme.text(
+                        &format!("Bonus objectives: {}", score.bonuses),
+                        TextRole::Body,
+                    ));
+                }
+            }
+            cmd.spawn(menu::button("Retry", &theme))
+                .insert(ResultsAction::Retry);
+            cmd.spawn(menu::button("Levels", &theme))
+                .insert(ResultsAction::LevelSelect);
+        });
+}
+
+fn results_action(
+    interaction_query: Query<
+        (&Interaction, &ResultsAction),
+        (Changed<Interaction>, With<Button>, Without<Disabled>),
+    >,
+    mut app_state: ResMut<NextState<AppState>>,
+    mut load_level: EventWriter<LoadNextLevel>,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            match action {
+                ResultsAction::Retry => {
+                    if let Some(level) = levels.get(&current_level.0) {
+                        load_level.write(LoadNextLevel(level.id.clone()));
+                        app_state.set(AppState::LoadingLevel);
+                    }
+                }
+                ResultsAction::LevelSelect => {
+                    app_state.set(AppState::LevelSelect);
+                }
+            }
+        }
+    }
+}
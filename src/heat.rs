@@ -0,0 +1,143 @@
+//! Heater and cooler markings, and steam rising off hot fluid
+
+use crate::game::GameEntity;
+use crate::pipes::{HOT_TEMPERATURE, Pipe};
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct HeatPlugin;
+
+impl Plugin for HeatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_heat_assets)
+            .add_systems(Update, (spawn_heat_bands, emit_steam, animate_steam));
+    }
+}
+
+/// Seconds between puffs of steam from each hot pipe.
+const STEAM_INTERVAL: f32 = 0.5;
+
+/// Seconds a puff of steam lives.
+const PUFF_TIME: f32 = 1.5;
+
+/// How fast steam rises, in world units per second.
+const PUFF_SPEED: f32 = 1.2;
+
+#[derive(Resource, Debug)]
+struct HeatAssets {
+    band: Handle<Mesh>,
+    heater: Handle<StandardMaterial>,
+    cooler: Handle<StandardMaterial>,
+    puff: Handle<Mesh>,
+    steam: Handle<StandardMaterial>,
+}
+
+/// Ticks the steam puffs off all hot pipes together.
+#[derive(Resource, Debug)]
+struct SteamTimer(Timer);
+
+/// Puff of steam drifting up from a hot pipe.
+#[derive(Component, Debug)]
+struct SteamPuff(Timer);
+
+fn initialize_heat_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut band_material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        })
+    };
+    let heater = band_material(Color::srgb(1.0, 0.35, 0.1));
+    let cooler = band_material(Color::srgb(0.3, 0.7, 1.0));
+    commands.insert_resource(HeatAssets {
+        band: meshes.add(Torus::new(0.45, 0.55)),
+        heater,
+        cooler,
+        puff: meshes.add(Sphere::new(0.2)),
+        steam: materials.add(StandardMaterial {
+            base_color: Color::srgba(1.0, 1.0, 1.0, 0.4),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+    commands.insert_resource(SteamTimer(Timer::from_seconds(
+        STEAM_INTERVAL,
+        TimerMode::Repeating,
+    )));
+}
+
+/// Adds a coloured band around each newly spawned heater and cooler.
+fn spawn_heat_bands(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    assets: Res<HeatAssets>,
+) {
+    for (entity, pipe) in &pipes {
+        let material = if pipe.heating > 0.0 {
+            assets.heater.clone()
+        } else if pipe.heating < 0.0 {
+            assets.cooler.clone()
+        } else {
+            continue;
+        };
+        // The torus lies flat, stand it up around the model axis running from side 0 to side 2
+        commands.entity(entity).with_child((
+            Mesh3d(assets.band.clone()),
+            MeshMaterial3d(material),
+            Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+        ));
+    }
+}
+
+fn emit_steam(
+    mut commands: Commands,
+    mut timer: ResMut<SteamTimer>,
+    pipes: Query<(&Pipe, &GlobalTransform)>,
+    assets: Res<HeatAssets>,
+    time: Res<Time>,
+) {
+    timer.0.tick(time.delta());
+    if !timer.0.just_finished() {
+        return;
+    }
+
+    for (pipe, transform) in &pipes {
+        if !pipe
+            .channels
+            .iter()
+            .any(|channel| channel.progress > 0.0 && channel.temperature >= HOT_TEMPERATURE)
+        {
+            continue;
+        }
+        let offset = Vec3::new(fastrand::f32() - 0.5, 0.8, fastrand::f32() - 0.5);
+        commands.spawn((
+            SteamPuff(Timer::from_seconds(PUFF_TIME, TimerMode::Once)),
+            Mesh3d(assets.puff.clone()),
+            MeshMaterial3d(assets.steam.clone()),
+            Transform::from_translation(transform.translation() + offset),
+            GameEntity,
+        ));
+    }
+}
+
+/// Lets steam rise and swell, then vanish.
+fn animate_steam(
+    mut commands: Commands,
+    mut puffs: Query<(Entity, &mut SteamPuff, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut puff, mut transform) in &mut puffs {
+        puff.0.tick(time.delta());
+        transform.translation.y += PUFF_SPEED * time.delta_secs();
+        transform.scale = Vec3::splat(1.0 + puff.0.fraction() * 1.5);
+        if puff.0.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! In-game heads-up display
+
+use crate::AppState;
+use crate::camera::LevelCamera;
+use crate::flow::{self, SinkGoal, SourceDelay};
+use crate::game::{FlowTime, GameEntity, Moves, PipeGameState, PrepareTimer};
+use crate::level::{CurrentLevel, GridCell, Level, LevelGrid};
+use crate::pipes::{Pipe, PipeArchetypes};
+use crate::placement::{self, PieceQueue};
+use crate::theme::{TextRole, UiTheme};
+use bevy::prelude::*;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            setup_hud.after(placement::setup_placement),
+        )
+        .add_systems(
+            Update,
+            (
+                update_state_text.run_if(state_changed::<PipeGameState>),
+                update_timer_text,
+                update_moves_text.run_if(resource_changed::<Moves>),
+                update_queue_text.run_if(resource_exists_and_changed::<PieceQueue>),
+                update_objectives,
+                update_source_countdowns,
+            )
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+#[derive(Component, Debug)]
+struct StateText;
+
+#[derive(Component, Debug)]
+struct TimerText;
+
+#[derive(Component, Debug)]
+struct MovesText;
+
+#[derive(Component, Debug)]
+struct QueueText;
+
+/// Line in the objective list, tracking the fill status of one sink.
+#[derive(Component, Debug)]
+struct SinkObjective(Entity);
+
+/// Label floating over a delayed source, counting down until it opens.
+#[derive(Component, Debug)]
+struct SourceCountdown(Entity);
+
+/// Height of source countdowns above their tile.
+const COUNTDOWN_HEIGHT: f32 = 1.5;
+
+fn setup_hud(
+    mut commands: Commands,
+    theme: UiTheme,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+    sinks: Query<(Entity, &Pipe, &GridCell, Option<&SinkGoal>)>,
+    delayed_sources: Query<(Entity, &SourceDelay)>,
+    queue: Option<Res<PieceQueue>>,
+    archetypes: Res<PipeArchetypes>,
+) {
+    let name = levels
+        .get(&current_level.0)
+        .map_or("Unnamed", |level| level.name.as_str());
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                row_gap: Val::Px(4.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.4)),
+            theme.border_radius(),
+            // Clicks go through to the pipes below
+            Pickable::IGNORE,
+            GameEntity,
+        ))
+        .with_children(|cmd| {
+            cmd.spawn((theme.text(name, TextRole::Title), Pickable::IGNORE));
+            cmd.spawn((theme.text("", TextRole::Body), StateText, Pickable::IGNORE));
+            cmd.spawn((theme.text("", TextRole::Body), TimerText, Pickable::IGNORE));
+            cmd.spawn((
+                theme.text("Moves: 0", TextRole::Body),
+                MovesText,
+                Pickable::IGNORE,
+            ));
+            if let Some(queue) = queue {
+                cmd.spawn((
+                    theme.text(&queue_label(&queue, &archetypes), TextRole::Body),
+                    QueueText,
+                    Pickable::IGNORE,
+                ));
+            }
+            for (entity, pipe, cell, goal) in &sinks {
+                if pipe.sink.is_some() {
+                    cmd.spawn((
+                        theme.text(&objective_label(pipe, cell, goal, None), TextRole::Body),
+                        SinkObjective(entity),
+                        Pickable::IGNORE,
+                    ));
+                }
+            }
+        });
+
+    // Placed by update_source_countdowns once the camera is known
+    for (entity, delay) in &delayed_sources {
+        commands.spawn((
+            theme.text(&countdown_label(delay), TextRole::Body),
+            Node {
+                position_type: PositionType::Absolute,
+                padding: UiRect::axes(Val::Px(6.0), Val::Px(2.0)),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.4)),
+            theme.border_radius(),
+            Visibility::Hidden,
+            SourceCountdown(entity),
+            Pickable::IGNORE,
+            GameEntity,
+        ));
+    }
+}
+
+fn update_state_text(
+    game_state: Res<State<PipeGameState>>,
+    mut texts: Query<&mut Text, With<StateText>>,
+) {
+    let label = match game_state.get() {
+        PipeGameState::Warmup => "Get ready",
+        PipeGameState::Prepare => "Arrange the pipes",
+        PipeGameState::Flowing => "Flowing",
+        PipeGameState::LevelWon => "Level complete",
+        PipeGameState::LevelFailed => "Level failed",
+    };
+    for mut text in &mut texts {
+        text.set_if_neq(Text::new(label));
+    }
+}
+
+/// Shows the prepare countdown, then the time since the sources opened.
+fn update_timer_text(
+    game_state: Res<State<PipeGameState>>,
+    prepare_timer: Option<Res<PrepareTimer>>,
+    flow_time: Option<Res<FlowTime>>,
+    mut texts: Query<&mut Text, With<TimerText>>,
+) {
+    let label = match (game_state.get(), prepare_timer, flow_time) {
+        (PipeGameState::Prepare, Some(timer), _) => format!(
+            "Sources open in {:.1}s (Space to start)",
+            timer.0.remaining_secs()
+        ),
+        (_, _, Some(flow_time)) => format!("Flow time: {:.1}s", flow_time.0.elapsed_secs()),
+        _ => String::new(),
+    };
+    for mut text in &mut texts {
+        text.set_if_neq(Text(label.clone()));
+    }
+}
+
+fn update_moves_text(moves: Res<Moves>, mut texts: Query<&mut Text, With<MovesText>>) {
+    for mut text in &mut texts {
+        text.set_if_neq(Text(format!("Moves: {}", moves.0)));
+    }
+}
+
+fn update_queue_text(
+    queue: Res<PieceQueue>,
+    archetypes: Res<PipeArchetypes>,
+    mut texts: Query<&mut Text, With<QueueText>>,
+) {
+    for mut text in &mut texts {
+        text.set_if_neq(Text(queue_label(&queue, &archetypes)));
+    }
+}
+
+fn queue_label(queue: &PieceQueue, archetypes: &PipeArchetypes) -> String {
+    let pieces: Vec<_> = queue
+        .pieces
+        .iter()
+        .map(|piece| piece.label(archetypes))
+        .collect();
+    format!("Next: {}", pieces.join(", "))
+}
+
+/// Shows the state of each sink, with the estimated time until it is full while the fluid flows.
+fn update_objectives(
+    pipes: Query<(Entity, &Pipe, &GridCell)>,
+    grid: Option<Res<LevelGrid>>,
+    game_state: Res<State<PipeGameState>>,
+    goals: Query<&SinkGoal>,
+    mut objectives: Query<(&SinkObjective, &mut Text)>,
+) {
+    let fill_times = match grid {
+        Some(grid) if *game_state.get() == PipeGameState::Flowing => {
+            flow::estimate_fill_times(&pipes, &grid)
+        }
+        _ => default(),
+    };
+    for (objective, mut text) in &mut objectives {
+        if let Ok((entity, pipe, cell)) = pipes.get(objective.0) {
+            let eta = fill_times.get(&entity).copied();
+            let goal = goals.get(entity).ok();
+            text.set_if_neq(Text(objective_label(pipe, cell, goal, eta)));
+        }
+    }
+}
+
+/// Keeps each source countdown over its tile, and removes it once the source has opened.
+fn update_source_countdowns(
+    mut commands: Commands,
+    sources: Query<(&GlobalTransform, &SourceDelay)>,
+    cameras: Query<(&Camera, &GlobalTransform), With<LevelCamera>>,
+    mut countdowns: Query<(
+        Entity,
+        &SourceCountdown,
+        &mut Node,
+        &mut Text,
+        &mut Visibility,
+        &ComputedNode,
+    )>,
+) {
+    let Ok((camera, camera_transform)) = cameras.single() else {
+        return;
+    };
+    for (entity, countdown, mut node, mut text, mut visibility, computed) in &mut countdowns {
+        let Ok((transform, delay)) = sources.get(countdown.0) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let above = transform.translation() + Vec3::Y * COUNTDOWN_HEIGHT;
+        let Ok(position) = camera.world_to_viewport(camera_transform, above) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        // Centered over the tile
+        let size = computed.size() * computed.inverse_scale_factor();
+        node.left = Val::Px(position.x - size.x / 2.0);
+        node.top = Val::Px(position.y - size.y / 2.0);
+        visibility.set_if_neq(Visibility::Inherited);
+        text.set_if_neq(Text(countdown_label(delay)));
+    }
+}
+
+fn countdown_label(delay: &SourceDelay) -> String {
+    format!("Opens in {:.1}s", delay.0.remaining_secs())
+}
+
+fn objective_label(
+    pipe: &Pipe,
+    cell: &GridCell,
+    goal: Option<&SinkGoal>,
+    eta: Option<f32>,
+) -> String {
+    let wanted = pipe.sink.as_deref().unwrap_or_default();
+    let status = match pipe.channels.first() {
+        None => "empty".to_string(),
+        Some(channel) if channel.fluid != wanted => format!("wrong fluid ({})", channel.fluid),
+        Some(channel)
+            if pipe
+                .sink_temperature
+                .is_some_and(|range| !range.contains(channel.temperature)) =>
+        {
+            format!("wrong temperature ({:.0}°C)", channel.temperature)
+        }
+        Some(channel) if channel.progress >= 1.0 => match goal {
+            Some(goal) if !goal.met => goal_status(pipe, goal),
+            _ => "filled".to_string(),
+        },
+        Some(channel) => format!("{:.0}%", channel.progress * 100.0),
+    };
+    let status = match eta.filter(|eta| *eta > 0.0) {
+        Some(eta) => format!("{status}, full in {eta:.1}s"),
+        None => status,
+    };
+    let status = match goal
+        .filter(|goal| !goal.met)
+        .and_then(|goal| goal.requirements.deadline)
+    {
+        Some(deadline) => format!("{status}, due by {deadline:.0}s"),
+        None => status,
+    };
+    let wanted = match pipe.sink_temperature {
+        Some(range) => format!("{wanted} ({:.0}-{:.0}°C)", range.min, range.max),
+        None => wanted.to_string(),
+    };
+    format!("{wanted} sink at {}: {status}", cell.0)
+}
+
+/// Progress of a full sink towards the requirements it hasn't met yet.
+fn goal_status(pipe: &Pipe, goal: &SinkGoal) -> String {
+    let volume = format!(
+        "{:.1}/{:.1} taken in",
+        goal.delivered(pipe),
+        goal.requirements.volume
+    );
+    match goal.requirements.hold {
+        Some(hold) => format!("{volume}, held {:.1}/{hold:.1}s", goal.held),
+        None => volume,
+    }
+}
@@ -1,8 +1,15 @@
 //! Level loading and related type defs
 
 use crate::AppState;
+use crate::flow::{SinkGoal, SourceDelay};
+use crate::game::{self, GameEntity};
 use crate::level::bytereader::BytesResourceReader;
-use crate::pipes::{InternalRouting, Pipe, PipeArchetypes, Slot};
+use crate::obstacles::{EMPTY_CELL_TILE, Obstacle};
+use crate::pipes::{
+    self, HEX_SIDES, InternalRouting, LargePipeArchetypes, LargePipeRoot, Pipe, PipeArchetypes,
+    PipePart, SIDES, Slot, SlotId,
+};
+use crate::valves::ValveTimer;
 use bevy::asset::io::Reader;
 use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
 use bevy::ecs::error::info;
@@ -21,13 +28,339 @@ pub struct Level {
     pub name: String,
     /// How many seconds until the input pipes activate?
     pub prepare_time: f32,
+    pub mode: GameMode,
+    /// Can pipes be dragged onto other cells to swap them?
+    pub allow_swap: bool,
+    pub leak_rule: LeakRule,
+    pub scoring: ScoringRules,
     pub data: LevelData,
 }
 
+/// How the player builds the pipe network, set with the `game_mode` map property.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    /// The grid is pre-filled and pipes are rotated into place (`rotate`)
+    #[default]
+    Rotate,
+    /// The grid starts with fixed tiles only and pipes are placed from a queue (`placement`)
+    Placement,
+    /// Pipes slide into neighbouring empty cells, like a 15-puzzle (`sliding`)
+    Sliding,
+}
+
+/// What happens when fluid spills out of the network, set with the `leaks` map property.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LeakRule {
+    /// The level fails as soon as fluid spills (`fail`)
+    #[default]
+    Fail,
+    /// The level fails once more than this volume has spilled, in pipe fills (`threshold`, with
+    /// the volume in `leak_volume`)
+    Threshold(f32),
+    /// Fluid may spill freely (`allow`)
+    Allow,
+}
+
+/// Scoring parameters, set per level with Tiled map properties.
+#[derive(Debug)]
+pub struct ScoringRules {
+    /// Minimum score for two and three stars (`star_2`, `star_3`)
+    pub star_thresholds: [u32; 2],
+    /// Seconds the flow should take at most; every second it is done sooner scores like prepare
+    /// time left (`par_time`)
+    pub par_time: Option<f32>,
+    /// Bonus objective: win in at most this many moves (`par_moves`)
+    pub par_moves: Option<u32>,
+    /// Bonus objective: fill at least this many pipes (`bonus_length`)
+    pub bonus_length: Option<u32>,
+}
+
+/// What a sink has to take in before it counts as done, set with properties of a map object on
+/// the sink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkRequirements {
+    /// Volume the sink must take in, in pipe fills, including the fluid filling it (`volume`)
+    pub volume: f32,
+    /// Seconds after the start of the flow by which the sink must be done (`deadline`)
+    pub deadline: Option<f32>,
+    /// Seconds the sink must stay full, with its fluid still coming in (`hold`)
+    pub hold: Option<f32>,
+}
+
+impl Default for SinkRequirements {
+    fn default() -> Self {
+        SinkRequirements {
+            volume: 1.0,
+            deadline: None,
+            hold: None,
+        }
+    }
+}
+
+/// Tile id for cells without a tile in the map, or painted with [`EMPTY_CELL_TILE`].
+pub const EMPTY_TILE: u32 = 0xF;
+
+/// Tiled's flag for turning a hex tile by 120°.
+///
+/// The tiled crate only strips the three flip flags from the global tile id, so this one stays in
+/// the id it reports. With the single tileset of the levels that is the tile id plus this bit.
+const HEX_TURN_120_FLAG: u32 = 0x1000_0000;
+
+/// Distance between neighbouring tile centers, in world units.
+pub const TILE_SIZE: f32 = 2.;
+
+/// Lattice the tiles of a level sit on, from the orientation of the map.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GridShape {
+    /// Square tiles with four sides (`orthogonal`)
+    #[default]
+    Square,
+    /// Pointy-top hex tiles with six sides, every odd row shifted half a tile to the right
+    /// (`hexagonal`, staggered along Y on odd rows)
+    Hex,
+}
+
+impl GridShape {
+    /// Number of sides of each tile.
+    pub fn sides(self) -> SlotId {
+        match self {
+            GridShape::Square => SIDES,
+            GridShape::Hex => HEX_SIDES,
+        }
+    }
+
+    /// Cell next to `cell` across the given world side.
+    pub fn neighbour(self, cell: IVec2, side: SlotId) -> IVec2 {
+        let offset = match self {
+            GridShape::Square => match side % SIDES {
+                0 => IVec2::NEG_Y,
+                1 => IVec2::X,
+                2 => IVec2::Y,
+                _ => IVec2::NEG_X,
+            },
+            GridShape::Hex => {
+                // Diagonal neighbours of odd rows are half a tile further right
+                let shift = cell.y.rem_euclid(2);
+                match side % HEX_SIDES {
+                    0 => IVec2::new(shift, -1),
+                    1 => IVec2::X,
+                    2 => IVec2::new(shift, 1),
+                    3 => IVec2::new(shift - 1, 1),
+                    4 => IVec2::NEG_X,
+                    _ => IVec2::new(shift - 1, -1),
+                }
+            }
+        };
+        cell + offset
+    }
+
+    /// Direction on the XZ plane from a tile center towards the given world side.
+    pub fn side_direction(self, side: SlotId) -> Vec2 {
+        match self {
+            GridShape::Square => self.neighbour(IVec2::ZERO, side).as_vec2(),
+            GridShape::Hex => pipes::hex_side_direction(side),
+        }
+    }
+
+    /// Distance between the centers of neighbouring rows, in world units.
+    fn row_spacing(self) -> f32 {
+        match self {
+            GridShape::Square => TILE_SIZE,
+            GridShape::Hex => TILE_SIZE * 3f32.sqrt() / 2.,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct LevelData {
     pub size: UVec2,
+    pub shape: GridShape,
     pub tiles: Vec<u32>,
+    /// Clockwise turns of each tile, by one side each
+    pub rotations: Vec<u8>,
+    /// Cells of paired portals, linked with the `portal` property of map objects
+    pub portals: Vec<[IVec2; 2]>,
+    /// Seconds after the start of the flow that sources open, set with the `delay` property of
+    /// map objects; sources not listed open right away
+    pub source_delays: Vec<(IVec2, f32)>,
+    /// Requirements of sinks that need more than being filled once
+    pub sink_requirements: Vec<(IVec2, SinkRequirements)>,
+    /// Seconds between valves flipping on their own during the flow, set with the `interval`
+    /// property of map objects; valves not listed only move when operated
+    pub valve_intervals: Vec<(IVec2, f32)>,
+}
+
+impl LevelData {
+    /// World-space XZ position of the tile center at the given index, with the grid centered on the origin.
+    pub fn tile_center(&self, index: usize) -> Vec2 {
+        self.cell_center(self.cell(index))
+    }
+
+    /// Grid cell of the tile at the given index.
+    pub fn cell(&self, index: usize) -> IVec2 {
+        IVec2::new(
+            (index as u32 % self.size.x) as i32,
+            (index as u32 / self.size.x) as i32,
+        )
+    }
+
+    /// World-space XZ position of the center of a grid cell.
+    pub fn cell_center(&self, cell: IVec2) -> Vec2 {
+        self.cell_position(cell) - self.level_offset()
+    }
+
+    /// Grid cell containing a world-space XZ position, if it is on the grid.
+    pub fn cell_at(&self, position: Vec2) -> Option<IVec2> {
+        let position = position + self.level_offset();
+        let row = (position.y / self.shape.row_spacing()).round() as i32;
+        let column = (position.x / TILE_SIZE).round() as i32;
+        // Hex rows interlock, so the closest center may be in a neighbouring row or column
+        let cell = (row - 1..=row + 1)
+            .flat_map(|y| (column - 1..=column + 1).map(move |x| IVec2::new(x, y)))
+            .min_by(|a, b| {
+                let a = self.cell_position(*a).distance_squared(position);
+                let b = self.cell_position(*b).distance_squared(position);
+                a.total_cmp(&b)
+            })?;
+        (cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size.as_ivec2()).all()).then_some(cell)
+    }
+
+    /// Cell of the portal paired with the one at `cell`.
+    pub fn portal_partner(&self, cell: IVec2) -> Option<IVec2> {
+        self.portals.iter().find_map(|[a, b]| {
+            if *a == cell {
+                Some(*b)
+            } else if *b == cell {
+                Some(*a)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Seconds after the start of the flow that the source at `cell` opens, if it is delayed.
+    pub fn source_delay(&self, cell: IVec2) -> Option<f32> {
+        self.source_delays
+            .iter()
+            .find(|(source, _)| *source == cell)
+            .map(|(_, delay)| *delay)
+    }
+
+    /// Seconds between flips of the valve at `cell`, if it flips on its own.
+    pub fn valve_interval(&self, cell: IVec2) -> Option<f32> {
+        self.valve_intervals
+            .iter()
+            .find(|(valve, _)| *valve == cell)
+            .map(|(_, interval)| *interval)
+    }
+
+    /// What the sink at `cell` has to take in, just filling it unless the map says otherwise.
+    pub fn sink_requirements(&self, cell: IVec2) -> SinkRequirements {
+        self.sink_requirements
+            .iter()
+            .find(|(sink, _)| *sink == cell)
+            .map(|(_, requirements)| *requirements)
+            .unwrap_or_default()
+    }
+
+    /// Extent of the whole grid on the XZ plane, including the outer half of the border tiles.
+    pub fn bounds(&self) -> Rect {
+        let size = match self.shape {
+            GridShape::Square => self.size.as_vec2() * TILE_SIZE,
+            // Pointy-top hexes reach a little further up and down than half the row spacing
+            GridShape::Hex => Vec2::new(
+                (self.size.x as f32 + if self.size.y > 1 { 0.5 } else { 0. }) * TILE_SIZE,
+                (self.size.y as f32 - 1.) * self.shape.row_spacing() + TILE_SIZE * 2. / 3f32.sqrt(),
+            ),
+        };
+        Rect::from_center_size(Vec2::ZERO, size)
+    }
+
+    /// XZ position of a cell center with the first cell at the origin.
+    fn cell_position(&self, cell: IVec2) -> Vec2 {
+        let shift = match self.shape {
+            GridShape::Square => 0.,
+            GridShape::Hex => cell.y.rem_euclid(2) as f32 / 2.,
+        };
+        Vec2::new(
+            (cell.x as f32 + shift) * TILE_SIZE,
+            cell.y as f32 * self.shape.row_spacing(),
+        )
+    }
+
+    /// Offset that centers the grid on the origin.
+    fn level_offset(&self) -> Vec2 {
+        let last = self.size.as_ivec2() - IVec2::ONE;
+        // The last row of a hex grid may not be shifted, but the one before it is
+        let width = self.cell_position(IVec2::new(last.x, last.y.min(1))).x;
+        Vec2::new(width, self.cell_position(last).y) / 2.
+    }
+}
+
+/// Grid position of a spawned tile.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridCell(pub IVec2);
+
+/// Lookup from grid cells to the tile entities of the current level.
+#[derive(Resource, Debug)]
+pub struct LevelGrid {
+    pub size: UVec2,
+    pub shape: GridShape,
+    cells: Vec<Option<Entity>>,
+}
+
+impl LevelGrid {
+    pub fn get(&self, cell: IVec2) -> Option<Entity> {
+        self.cells[self.index(cell)?]
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        self.index(cell).is_some()
+    }
+
+    /// Cell next to `cell` across the given world side, which may be off the grid.
+    pub fn neighbour(&self, cell: IVec2, side: SlotId) -> IVec2 {
+        self.shape.neighbour(cell, side)
+    }
+
+    /// Empties a cell; does nothing for cells outside the grid.
+    pub fn clear(&mut self, cell: IVec2) {
+        if let Some(index) = self.index(cell) {
+            self.cells[index] = None;
+        }
+    }
+
+    /// Stores the tile entity for a cell; does nothing for cells outside the grid.
+    pub fn set(&mut self, cell: IVec2, entity: Entity) {
+        if let Some(index) = self.index(cell) {
+            self.cells[index] = Some(entity);
+        }
+    }
+
+    /// Exchanges the tiles of two cells, either of which may be empty.
+    pub fn swap(&mut self, a: IVec2, b: IVec2) {
+        if let (Some(a), Some(b)) = (self.index(a), self.index(b)) {
+            self.cells.swap(a, b);
+        }
+    }
+
+    /// Cells that have no tile in them.
+    pub fn empty_cells(&self) -> impl Iterator<Item = IVec2> + '_ {
+        let width = self.size.x as i32;
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, entity)| entity.is_none())
+            .map(move |(index, _)| IVec2::new(index as i32 % width, index as i32 / width))
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        if cell.x < 0 || cell.y < 0 || cell.x >= self.size.x as i32 || cell.y >= self.size.y as i32
+        {
+            return None;
+        }
+        Some((cell.y * self.size.x as i32 + cell.x) as usize)
+    }
 }
 
 /// Event for triggering the loading of a new level.
@@ -53,7 +386,9 @@ impl Plugin for LevelPlugin {
                 PreUpdate,
                 (
                     begin_loading_level.run_if(on_event::<LoadNextLevel>),
-                    wait_for_level_data.run_if(resource_exists::<LevelInLoading>),
+                    wait_for_level_data.run_if(
+                        resource_exists::<LevelInLoading>.and(in_state(AppState::LoadingLevel)),
+                    ),
                     cleanup.run_if(on_event::<LevelLoaded>),
                 )
                     .chain(),
@@ -78,29 +413,209 @@ fn wait_for_level_data(
     level_in_loading: Res<LevelInLoading>,
     mut loaded_events: EventWriter<LevelLoaded>,
     pipe_archetypes: Res<PipeArchetypes>,
+    large_pipe_archetypes: Res<LargePipeArchetypes>,
 ) {
     if let Some(level) = level_assets.get(&level_in_loading.0) {
         info!("Level asset loaded, spawning tiles");
-        // spawn tiles
-        let level_offset = Vec2::new(level.data.size.x as f32, level.data.size.y as f32) / 2.;
+        let mut grid = LevelGrid {
+            size: level.data.size,
+            shape: level.data.shape,
+            cells: vec![None; level.data.tiles.len()],
+        };
 
+        // Large pipes claim all the cells they cover, their tile being in the bottom-left one
+        let mut large_pipes = Vec::new();
         for (index, tile) in level.data.tiles.iter().enumerate() {
-            let row = ((index as f32) / level.data.size.x as f32).floor();
-            let column = ((index as f32) % level.data.size.x as f32).floor();
-            let tile_center = Vec2::new(column * 2., row * 2.) - level_offset;
+            let Some(archetype) = large_pipe_archetypes.get(tile) else {
+                continue;
+            };
+            if level.data.shape != GridShape::Square {
+                warn!(
+                    "Large pipe {} at {} needs a square grid",
+                    tile,
+                    level.data.cell(index)
+                );
+                continue;
+            }
+            let rotation = level.data.rotations[index];
+            let offsets: Vec<_> = archetype.parts.iter().map(|(offset, _)| *offset).collect();
+            let footprint = pipes::turn_footprint(&offsets, rotation);
+            let height = footprint
+                .iter()
+                .map(|offset| offset.y)
+                .max()
+                .unwrap_or_default();
+            let origin = level.data.cell(index) - IVec2::new(0, height);
+            if footprint.iter().any(|offset| {
+                grid.index(origin + *offset)
+                    .is_none_or(|cell| grid.cells[cell].is_some())
+            }) {
+                warn!(
+                    "Large pipe {} at {} doesn't fit",
+                    tile,
+                    level.data.cell(index)
+                );
+                continue;
+            }
+            let parts: Vec<_> = footprint
+                .iter()
+                .map(|offset| {
+                    let part = commands.spawn_empty().id();
+                    grid.set(origin + *offset, part);
+                    part
+                })
+                .collect();
+            large_pipes.push((archetype, origin, rotation, parts));
+        }
 
+        // Reserve the tile entities first, so portals can link to partners spawned after them
+        let mut entities = vec![None; level.data.tiles.len()];
+        for (index, tile) in level.data.tiles.iter().enumerate() {
             if let Some(pipe) = pipe_archetypes.get(tile) {
+                if pipe.sides() != level.data.shape.sides() {
+                    warn!(
+                        "Pipe {} at {} doesn't fit the grid",
+                        tile,
+                        level.data.cell(index)
+                    );
+                    continue;
+                }
+            } else if Obstacle::from_tile(*tile).is_none() {
+                continue;
+            }
+            if grid.cells[index].is_some() {
+                warn!(
+                    "Pipe {} at {} is under a large pipe",
+                    tile,
+                    level.data.cell(index)
+                );
+                continue;
+            }
+            let entity = commands.spawn_empty().id();
+            grid.cells[index] = Some(entity);
+            entities[index] = Some(entity);
+        }
+
+        // spawn tiles
+        for (index, tile) in level.data.tiles.iter().enumerate() {
+            let tile_center = level.data.tile_center(index);
+            let cell = level.data.cell(index);
+
+            if let (Some(pipe), Some(entity)) = (pipe_archetypes.get(tile), entities[index]) {
                 info!("Spawning pipe {}", tile);
-                commands.spawn((
-                    pipe.clone(),
-                    SceneRoot(pipe.model.clone()),
-                    Transform::from_xyz(tile_center.x, 0., tile_center.y),
+                let mut pipe = pipe.clone();
+                pipe.rotation = level.data.rotations[index];
+                // Tiles from the map are fixed obstacles when the player places the pipes
+                pipe.locked |= level.mode == GameMode::Placement;
+                if let Some(portal) = &mut pipe.portal {
+                    portal.partner = level
+                        .data
+                        .portal_partner(cell)
+                        .and_then(|partner| grid.index(partner))
+                        .filter(|partner| {
+                            pipe_archetypes
+                                .get(&level.data.tiles[*partner])
+                                .is_some_and(|partner| partner.portal.is_some())
+                        })
+                        .and_then(|partner| entities[partner]);
+                    if portal.partner.is_none() {
+                        warn!("Portal at {} has no partner", cell);
+                    }
+                }
+                let mut tile = commands.entity(entity);
+                if pipe.source.is_some()
+                    && let Some(delay) = level.data.source_delay(cell)
+                {
+                    tile.insert(SourceDelay(Timer::from_seconds(delay, TimerMode::Once)));
+                }
+                if pipe.sink.is_some() {
+                    tile.insert(SinkGoal::new(level.data.sink_requirements(cell)));
+                }
+                if pipe.valve.is_some()
+                    && let Some(interval) = level.data.valve_interval(cell)
+                {
+                    tile.insert(ValveTimer(Timer::from_seconds(
+                        interval,
+                        TimerMode::Repeating,
+                    )));
+                }
+                pipes::spawn_pipe_visual(&mut tile, &pipe);
+                tile.insert((
+                    Transform::from_xyz(tile_center.x, 0., tile_center.y)
+                        .with_rotation(pipes::rotation_quat(pipe.rotation, pipe.sides())),
+                    pipe,
+                    GridCell(cell),
+                    GameEntity,
+                ))
+                .observe(game::rotate_pipe);
+            } else if let (Some(obstacle), Some(entity)) =
+                (Obstacle::from_tile(*tile), entities[index])
+            {
+                commands.entity(entity).insert((
+                    Transform::from_xyz(tile_center.x, 0., tile_center.y).with_rotation(
+                        pipes::rotation_quat(level.data.rotations[index], level.data.shape.sides()),
+                    ),
+                    Visibility::default(),
+                    obstacle,
+                    GridCell(cell),
+                    GameEntity,
                 ));
-            } else {
+            } else if *tile != EMPTY_TILE
+                && !pipe_archetypes.contains_key(tile)
+                && Obstacle::from_tile(*tile).is_none()
+                && !large_pipe_archetypes.contains_key(tile)
+            {
                 warn!("Level has unknown pipe: {}", tile);
             }
         }
 
+        for (archetype, origin, rotation, parts) in large_pipes {
+            info!("Spawning large pipe at {}", origin);
+            let offsets: Vec<_> = archetype.parts.iter().map(|(offset, _)| *offset).collect();
+            let cells: Vec<_> = pipes::turn_footprint(&offsets, rotation)
+                .into_iter()
+                .map(|offset| origin + offset)
+                .collect();
+            let center = cells
+                .iter()
+                .map(|cell| level.data.cell_center(*cell))
+                .sum::<Vec2>()
+                / cells.len() as f32;
+            let root = commands
+                .spawn((
+                    SceneRoot(archetype.model.clone()),
+                    Transform::from_xyz(center.x, 0., center.y)
+                        .with_rotation(pipes::rotation_quat(rotation, SIDES))
+                        .with_scale(archetype.model_scale),
+                    LargePipeRoot {
+                        origin,
+                        rotation,
+                        parts: offsets.iter().copied().zip(parts.iter().copied()).collect(),
+                        locked: level.mode == GameMode::Placement,
+                        exchanges_heat: archetype.exchanges_heat,
+                    },
+                    GameEntity,
+                ))
+                .observe(game::rotate_large_pipe)
+                .id();
+            let parts = archetype.parts.iter().zip(cells.into_iter().zip(parts));
+            for ((_, pipe), (cell, entity)) in parts {
+                let mut pipe = pipe.clone();
+                pipe.rotation = rotation;
+                let center = level.data.cell_center(cell);
+                commands.entity(entity).insert((
+                    Transform::from_xyz(center.x, 0., center.y)
+                        .with_rotation(pipes::rotation_quat(rotation, SIDES)),
+                    Visibility::default(),
+                    pipe,
+                    GridCell(cell),
+                    PipePart(root),
+                    GameEntity,
+                ));
+            }
+        }
+        commands.insert_resource(grid);
+
         // send event
         loaded_events.write(LevelLoaded(level_in_loading.0.clone()));
         commands.insert_resource(CurrentLevel(level_in_loading.0.clone()));
@@ -152,16 +667,97 @@ impl AssetLoader for LevelLoader {
             .as_tile_layer()
             .ok_or(LevelError::MissingLayer)?;
 
+        let shape = if map.orientation == tiled::Orientation::Hexagonal {
+            GridShape::Hex
+        } else {
+            GridShape::Square
+        };
         let mut tiles = Vec::with_capacity((map.width * map.height) as usize);
+        let mut rotations = Vec::with_capacity((map.width * map.height) as usize);
 
         for y in 0..map.height {
             for x in 0..map.width {
                 if let Some(tile) = tile_layer
                     .get_tile(x as i32, y as i32) {
-                    tiles.push(tile.id());
+                    let turned_120 = tile.id() & HEX_TURN_120_FLAG != 0;
+                    // Cells painted empty are the same as cells left without a tile
+                    tiles.push(match tile.id() & !HEX_TURN_120_FLAG {
+                        EMPTY_CELL_TILE => EMPTY_TILE,
+                        id => id,
+                    });
+                    // Tiled stores rotations as combinations of flips
+                    let flips = (tile.flip_h, tile.flip_v, tile.flip_d);
+                    rotations.push(match shape {
+                        GridShape::Square => match flips {
+                            (true, false, true) => 1,
+                            (true, true, false) => 2,
+                            (false, true, true) => 3,
+                            _ => 0,
+                        },
+                        // On hex maps the anti-diagonal flag turns by 60°, the 120° flag by 120°
+                        // and both of the other flips together by 180°
+                        GridShape::Hex => match (flips, turned_120) {
+                            ((false, false, false), false) => 0,
+                            ((false, false, true), false) => 1,
+                            ((false, false, false), true) => 2,
+                            ((true, true, false), false) => 3,
+                            ((true, true, true), false) => 4,
+                            ((true, true, false), true) => 5,
+                            _ => {
+                                // Mirrored pipes would route the fluid differently from their
+                                // models
+                                warn!("Tile at {}, {} is mirrored, loading it unturned", x, y);
+                                0
+                            }
+                        },
+                    });
                 } else {
-                    tiles.push(0xF);
+                    tiles.push(EMPTY_TILE);
+                    rotations.push(0);
+                }
+            }
+        }
+
+        // Tile objects go into the cell of their bottom-left corner, which is where large pipes
+        // have their tile too
+        let tile_size = Vec2::new(map.tile_width as f32, map.tile_height as f32);
+        for layer in map.layers() {
+            let Some(object_layer) = layer.as_object_layer() else {
+                continue;
+            };
+            for object in object_layer.objects() {
+                let Some(tile) = object.get_tile() else {
+                    continue;
+                };
+                let IVec2 { x, y } = match shape {
+                    GridShape::Square => IVec2::new(
+                        (object.x / tile_size.x).floor() as i32,
+                        (object.y / tile_size.y).ceil() as i32 - 1,
+                    ),
+                    // Hex tile objects can't span several cells, so they go by their center
+                    GridShape::Hex => hex_cell(
+                        tile_size,
+                        Vec2::new(object.x + tile_size.x / 2., object.y - tile_size.y / 2.),
+                    ),
+                };
+                if x < 0 || y < 0 || x >= map.width as i32 || y >= map.height as i32 {
+                    warn!("Tile object {} is outside the map", object.id());
+                    continue;
                 }
+                let index = (y as u32 * map.width + x as u32) as usize;
+                if tiles[index] != EMPTY_TILE {
+                    warn!(
+                        "Tile object {} replaces the tile at {}, {}",
+                        object.id(),
+                        x,
+                        y
+                    );
+                }
+                tiles[index] = tile.id();
+                // Tiled turns objects clockwise in degrees
+                let step = 360.0 / shape.sides() as f32;
+                rotations[index] = ((object.rotation / step).round() as i32)
+                    .rem_euclid(shape.sides() as i32) as u8;
             }
         }
 
@@ -175,10 +771,42 @@ impl AssetLoader for LevelLoader {
                     _ => None,
                 })
                 .unwrap_or("Unnamed".into()),
-            prepare_time: 0.0,
+            prepare_time: float_property(&map.properties, "prepare_time").unwrap_or(10.0),
+            mode: match map.properties.get("game_mode") {
+                Some(PropertyValue::StringValue(mode)) if mode == "placement" => {
+                    GameMode::Placement
+                }
+                Some(PropertyValue::StringValue(mode)) if mode == "sliding" => GameMode::Sliding,
+                _ => GameMode::Rotate,
+            },
+            allow_swap: bool_property(&map.properties, "allow_swap").unwrap_or(false),
+            leak_rule: match map.properties.get("leaks") {
+                Some(PropertyValue::StringValue(rule)) if rule == "threshold" => {
+                    LeakRule::Threshold(
+                        float_property(&map.properties, "leak_volume").unwrap_or(1.0),
+                    )
+                }
+                Some(PropertyValue::StringValue(rule)) if rule == "allow" => LeakRule::Allow,
+                _ => LeakRule::Fail,
+            },
+            scoring: ScoringRules {
+                star_thresholds: [
+                    uint_property(&map.properties, "star_2").unwrap_or(500),
+                    uint_property(&map.properties, "star_3").unwrap_or(1000),
+                ],
+                par_time: float_property(&map.properties, "par_time"),
+                par_moves: uint_property(&map.properties, "par_moves"),
+                bonus_length: uint_property(&map.properties, "bonus_length"),
+            },
             data: LevelData {
-                size: UVec2::new(map.width, map.width),
+                size: UVec2::new(map.width, map.height),
+                shape,
                 tiles,
+                rotations,
+                portals: portal_pairs(&map),
+                source_delays: source_delays(&map),
+                sink_requirements: sink_requirements(&map),
+                valve_intervals: valve_intervals(&map),
             },
         };
 
@@ -190,6 +818,167 @@ impl AssetLoader for LevelLoader {
     }
 }
 
+/// Cells of the portals paired by objects in the object layers of the map.
+///
+/// A point or rectangle object on a portal tile pairs it through its `portal` property: either an
+/// object property linking to an object on the partner, or a number shared with the object on it.
+fn portal_pairs(map: &tiled::Map) -> Vec<[IVec2; 2]> {
+    let mut objects = Vec::new();
+    for layer in map.layers() {
+        let Some(object_layer) = layer.as_object_layer() else {
+            continue;
+        };
+        for object in object_layer.objects() {
+            let cell = marker_cell(map, &object);
+            objects.push((object.id(), cell, object.properties.get("portal").cloned()));
+        }
+    }
+
+    let mut pairs: Vec<[IVec2; 2]> = Vec::new();
+    for (index, (_, cell, link)) in objects.iter().enumerate() {
+        let partner = match link {
+            Some(PropertyValue::ObjectValue(target)) => {
+                objects.iter().find(|(id, ..)| id == target)
+            }
+            Some(PropertyValue::IntValue(_)) => objects
+                .iter()
+                .skip(index + 1)
+                .find(|(_, _, other)| other == link),
+            _ => continue,
+        };
+        let Some((_, partner, _)) = partner else {
+            warn!("Portal at {} links to nothing", cell);
+            continue;
+        };
+        // Links from both ends of a pair are fine, linking a portal twice is not
+        if pairs.contains(&[*partner, *cell]) {
+            continue;
+        }
+        if pairs
+            .iter()
+            .any(|pair| pair.contains(cell) || pair.contains(partner))
+        {
+            warn!("Portal at {} or {} is already paired", cell, partner);
+            continue;
+        }
+        pairs.push([*cell, *partner]);
+    }
+    pairs
+}
+
+/// Delays of the sources marked by objects in the object layers of the map.
+///
+/// A point or rectangle object on a source tile delays its opening by the seconds in its `delay`
+/// property, counted from the start of the flow.
+fn source_delays(map: &tiled::Map) -> Vec<(IVec2, f32)> {
+    let mut delays = Vec::new();
+    for layer in map.layers() {
+        let Some(object_layer) = layer.as_object_layer() else {
+            continue;
+        };
+        for object in object_layer.objects() {
+            if let Some(delay) =
+                float_property(&object.properties, "delay").filter(|delay| *delay > 0.0)
+            {
+                delays.push((marker_cell(map, &object), delay));
+            }
+        }
+    }
+    delays
+}
+
+/// Requirements of the sinks marked by objects in the object layers of the map.
+///
+/// A point or rectangle object on a sink tile sets its requirements with the `volume`,
+/// `deadline` and `hold` properties, see [`SinkRequirements`].
+fn sink_requirements(map: &tiled::Map) -> Vec<(IVec2, SinkRequirements)> {
+    let mut sinks = Vec::new();
+    for layer in map.layers() {
+        let Some(object_layer) = layer.as_object_layer() else {
+            continue;
+        };
+        for object in object_layer.objects() {
+            let volume = float_property(&object.properties, "volume");
+            let deadline = float_property(&object.properties, "deadline");
+            let hold = float_property(&object.properties, "hold");
+            if volume.is_none() && deadline.is_none() && hold.is_none() {
+                continue;
+            }
+            let requirements = SinkRequirements {
+                volume: volume.filter(|volume| *volume > 0.0).unwrap_or(1.0),
+                deadline: deadline.filter(|deadline| *deadline > 0.0),
+                hold: hold.filter(|hold| *hold > 0.0),
+            };
+            sinks.push((marker_cell(map, &object), requirements));
+        }
+    }
+    sinks
+}
+
+/// Intervals of the valves marked by objects in the object layers of the map.
+///
+/// A point or rectangle object on a gate valve or diverter makes it flip on its own every
+/// `interval` seconds during the flow.
+fn valve_intervals(map: &tiled::Map) -> Vec<(IVec2, f32)> {
+    let mut intervals = Vec::new();
+    for layer in map.layers() {
+        let Some(object_layer) = layer.as_object_layer() else {
+            continue;
+        };
+        for object in object_layer.objects() {
+            if let Some(interval) =
+                float_property(&object.properties, "interval").filter(|interval| *interval > 0.0)
+            {
+                intervals.push((marker_cell(map, &object), interval));
+            }
+        }
+    }
+    intervals
+}
+
+/// Cell of the map under the position of a point or rectangle object.
+fn marker_cell(map: &tiled::Map, object: &tiled::ObjectData) -> IVec2 {
+    let position = Vec2::new(object.x, object.y);
+    let tile_size = Vec2::new(map.tile_width as f32, map.tile_height as f32);
+    if map.orientation == tiled::Orientation::Hexagonal {
+        hex_cell(tile_size, position)
+    } else {
+        (position / tile_size).floor().as_ivec2()
+    }
+}
+
+/// Cell of a hex map with tiles of `tile_size` under a position in map pixels.
+///
+/// Assumes regular pointy-top hexes, so rows are three quarters of a tile apart. Positions on the
+/// slanted edges between rows go to the row whose middle band is closer.
+fn hex_cell(tile_size: Vec2, position: Vec2) -> IVec2 {
+    let row = ((position.y - tile_size.y / 8.) / (tile_size.y * 0.75)).floor() as i32;
+    let shift = if row.rem_euclid(2) == 1 { 0.5 } else { 0. };
+    IVec2::new((position.x / tile_size.x - shift).floor() as i32, row)
+}
+
+fn float_property(properties: &tiled::Properties, name: &str) -> Option<f32> {
+    match properties.get(name)? {
+        PropertyValue::FloatValue(f) => Some(*f),
+        PropertyValue::IntValue(i) => Some(*i as f32),
+        _ => None,
+    }
+}
+
+fn bool_property(properties: &tiled::Properties, name: &str) -> Option<bool> {
+    match properties.get(name)? {
+        PropertyValue::BoolValue(b) => Some(*b),
+        _ => None,
+    }
+}
+
+fn uint_property(properties: &tiled::Properties, name: &str) -> Option<u32> {
+    match properties.get(name)? {
+        PropertyValue::IntValue(i) => u32::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
 mod bytereader {
     // Taken from https://github.com/adrien-bon/bevy_ecs_tiled/blob/main/src/reader.rs
     use bevy::asset::LoadContext;
@@ -229,3 +1018,128 @@ mod bytereader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipes::opposite_side;
+
+    fn level_data(shape: GridShape, size: UVec2) -> LevelData {
+        let cells = (size.x * size.y) as usize;
+        LevelData {
+            size,
+            shape,
+            tiles: vec![EMPTY_TILE; cells],
+            rotations: vec![0; cells],
+            portals: Vec::new(),
+            source_delays: Vec::new(),
+            sink_requirements: Vec::new(),
+            valve_intervals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn square_neighbours() {
+        let cell = IVec2::new(2, 2);
+        let neighbours: Vec<_> = (0..SIDES)
+            .map(|side| GridShape::Square.neighbour(cell, side))
+            .collect();
+        assert_eq!(
+            neighbours,
+            [
+                IVec2::new(2, 1),
+                IVec2::new(3, 2),
+                IVec2::new(2, 3),
+                IVec2::new(1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn hex_neighbours_interlock() {
+        let even = IVec2::new(2, 2);
+        let neighbours: Vec<_> = (0..HEX_SIDES)
+            .map(|side| GridShape::Hex.neighbour(even, side))
+            .collect();
+        assert_eq!(
+            neighbours,
+            [
+                IVec2::new(2, 1),
+                IVec2::new(3, 2),
+                IVec2::new(2, 3),
+                IVec2::new(1, 3),
+                IVec2::new(1, 2),
+                IVec2::new(1, 1),
+            ]
+        );
+
+        // Odd rows are shifted half a tile right
+        let odd = IVec2::new(2, 1);
+        let neighbours: Vec<_> = (0..HEX_SIDES)
+            .map(|side| GridShape::Hex.neighbour(odd, side))
+            .collect();
+        assert_eq!(
+            neighbours,
+            [
+                IVec2::new(3, 0),
+                IVec2::new(3, 1),
+                IVec2::new(3, 2),
+                IVec2::new(2, 2),
+                IVec2::new(1, 1),
+                IVec2::new(2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn neighbours_lead_back_through_the_opposite_side() {
+        for shape in [GridShape::Square, GridShape::Hex] {
+            let sides = shape.sides();
+            for cell in [IVec2::new(2, 2), IVec2::new(2, 1)] {
+                for side in 0..sides {
+                    let neighbour = shape.neighbour(cell, side);
+                    assert_eq!(shape.neighbour(neighbour, opposite_side(side, sides)), cell);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cell_at_tile_centers() {
+        for shape in [GridShape::Square, GridShape::Hex] {
+            let data = level_data(shape, UVec2::new(4, 3));
+            for index in 0..data.tiles.len() {
+                let center = data.tile_center(index);
+                assert_eq!(data.cell_at(center), Some(data.cell(index)));
+                assert_eq!(
+                    data.cell_at(center + Vec2::new(0.4, -0.3)),
+                    Some(data.cell(index))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cell_at_off_the_grid() {
+        for shape in [GridShape::Square, GridShape::Hex] {
+            let data = level_data(shape, UVec2::new(4, 3));
+            let bounds = data.bounds();
+            assert_eq!(data.cell_at(bounds.max + Vec2::ONE), None);
+            assert_eq!(data.cell_at(bounds.min - Vec2::ONE), None);
+        }
+    }
+
+    #[test]
+    fn hex_cell_of_map_positions() {
+        let tile_size = Vec2::splat(16.);
+        // Centers are 12 px apart vertically, and odd rows 8 px further right
+        assert_eq!(hex_cell(tile_size, Vec2::new(8., 8.)), IVec2::new(0, 0));
+        assert_eq!(hex_cell(tile_size, Vec2::new(16., 20.)), IVec2::new(0, 1));
+        assert_eq!(hex_cell(tile_size, Vec2::new(40., 32.)), IVec2::new(2, 2));
+        // The left end of an odd row is in the cell before its first one
+        assert_eq!(hex_cell(tile_size, Vec2::new(4., 20.)), IVec2::new(-1, 1));
+        // Slanted edges between rows go to the row whose middle band is closer
+        assert_eq!(hex_cell(tile_size, Vec2::new(8., 13.)), IVec2::new(0, 0));
+        assert_eq!(hex_cell(tile_size, Vec2::new(8., 15.)), IVec2::new(0, 1));
+    }
+}
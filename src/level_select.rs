@@ -1,9 +1,11 @@
 //! Level select
 
 use crate::AppState;
-use crate::assets::UiAssets;
 use crate::level::LoadNextLevel;
-use crate::menu::{self, MenuItem};
+use crate::menu::{self, Disabled, MenuItem};
+use crate::scoring::{self, Progress};
+use crate::theme::UiTheme;
+use bevy::input_focus::tab_navigation::TabGroup;
 use bevy::prelude::*;
 
 
@@ -28,7 +30,7 @@ enum MenuAction {
 }
 
 
-fn setup_level_select(mut commands: Commands, assets: Res<UiAssets>) {
+fn setup_level_select(mut commands: Commands, theme: UiTheme, progress: Res<Progress>) {
     commands
         .spawn((
             Node {
@@ -45,20 +47,35 @@ fn setup_level_select(mut commands: Commands, assets: Res<UiAssets>) {
                 ..default()
             },
             MenuItem,
+            TabGroup::default(),
         ))
         .with_children(|cmd| {
             for i in 1..=3 {
                 for j in 1..=6 {
                     let lname = format!("{}-{}", i, j);
-                    cmd.spawn(menu::button_small(&lname, &assets))
-                        .insert(MenuAction::PlayLevel(lname));
+                    let stars = progress
+                        .get(&format!("levels/{lname}.tmx"))
+                        .map_or(0, |best| best.stars);
+                    cmd.spawn(Node {
+                        row_gap: Val::Px(4.0),
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    })
+                    .with_children(|cmd| {
+                        cmd.spawn(menu::button_small(&lname, &theme))
+                            .insert(MenuAction::PlayLevel(lname));
+                        cmd.spawn(scoring::star_row(stars, 12.0));
+                    });
                 }
             }
         });
 }
 
 fn ls_action(
-    interaction_query: Query<(&Interaction, &MenuAction), (Changed<Interaction>, With<Button>)>,
+    interaction_query: Query<
+        (&Interaction, &MenuAction),
+        (Changed<Interaction>, With<Button>, Without<Disabled>),
+    >,
     mut app_exit_events: EventWriter<AppExit>,
     mut app_state: ResMut<NextState<AppState>>,
     mut load_level: EventWriter<LoadNextLevel>,
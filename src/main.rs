@@ -1,16 +1,53 @@
 mod assets;
+mod audio;
+mod camera;
+mod fill;
+mod flow;
 mod game;
+mod heat;
+mod hud;
 mod level_select;
 mod level;
 mod menu;
+mod obstacles;
+mod options;
 mod pipes;
+mod placement;
+mod portals;
+mod pumps;
+mod scoring;
+mod sinks;
+mod sliding;
+mod spills;
+mod storage;
+mod swap;
+mod theme;
+mod valves;
 
 use crate::assets::AssetsPlugin;
+use crate::audio::GameAudioPlugin;
+use crate::camera::LevelCameraPlugin;
+use crate::fill::FillPlugin;
+use crate::flow::FlowPlugin;
 use crate::game::PipeGamePlugin;
+use crate::heat::HeatPlugin;
+use crate::hud::HudPlugin;
 use crate::level_select::LevelSelectPlugin;
 use crate::level::LevelPlugin;
 use crate::menu::MenuPlugin;
+use crate::obstacles::ObstaclesPlugin;
+use crate::options::OptionsPlugin;
 use crate::pipes::PipePlugin;
+use crate::placement::PlacementPlugin;
+use crate::portals::PortalsPlugin;
+use crate::pumps::PumpsPlugin;
+use crate::scoring::ScoringPlugin;
+use crate::sinks::SinksPlugin;
+use crate::sliding::SlidingPlugin;
+use crate::spills::SpillsPlugin;
+use crate::swap::SwapPlugin;
+use crate::theme::ThemePlugin;
+use crate::valves::ValvesPlugin;
 use bevy::asset::AssetMetaCheck;
 use bevy::prelude::*;
 
@@ -27,11 +64,31 @@ fn main() {
         .init_state::<AppState>()
         .add_plugins((
             AssetsPlugin,
+            ThemePlugin,
             MenuPlugin,
+            OptionsPlugin,
             LevelSelectPlugin,
             LevelPlugin,
             PipePlugin,
             PipeGamePlugin,
+            LevelCameraPlugin,
+            FlowPlugin,
+            FillPlugin,
+            GameAudioPlugin,
+            ScoringPlugin,
+            HudPlugin,
+            PlacementPlugin,
+        ))
+        .add_plugins((
+            SwapPlugin,
+            SlidingPlugin,
+            ValvesPlugin,
+            HeatPlugin,
+            PumpsPlugin,
+            SpillsPlugin,
+            PortalsPlugin,
+            ObstaclesPlugin,
+            SinksPlugin,
         ))
         .add_systems(Startup, setup)
         .run();
@@ -42,6 +99,7 @@ pub enum AppState {
     #[default]
     LoadingAssets,
     MainMenu,
+    Options,
     LevelSelect,
     LoadingLevel,
     InGame,
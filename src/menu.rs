@@ -1,8 +1,11 @@
 //! Game menu
 
 use crate::AppState;
-use crate::assets::UiAssets;
+use crate::audio::{PlaySfx, Sfx};
 use crate::level::LoadNextLevel;
+use crate::theme::{TextRole, ThemedButton, UiTheme};
+use bevy::input_focus::InputFocus;
+use bevy::input_focus::tab_navigation::{TabGroup, TabIndex};
 use bevy::prelude::*;
 
 pub struct MenuPlugin;
@@ -22,13 +25,18 @@ impl Plugin for MenuPlugin {
 #[derive(Component, Debug)]
 pub struct MenuItem;
 
+/// Marker for buttons that can't be pressed right now.
+#[derive(Component, Debug)]
+pub struct Disabled;
+
 #[derive(Component, Debug)]
 enum MenuAction {
     StartGame,
+    Options,
     Quit,
 }
 
-fn setup_main_menu(mut commands: Commands, assets: Res<UiAssets>) {
+fn setup_main_menu(mut commands: Commands, theme: UiTheme) {
     commands
         .spawn((
             Node {
@@ -41,6 +49,7 @@ fn setup_main_menu(mut commands: Commands, assets: Res<UiAssets>) {
                 ..default()
             },
             MenuItem,
+            TabGroup::default(),
         ))
         .with_children(|cmd| {
             cmd.spawn((
@@ -54,111 +63,113 @@ fn setup_main_menu(mut commands: Commands, assets: Res<UiAssets>) {
                 },
                 MenuItem,
                 children![(
-                    Text::new("Piping Hot"),
-                    TextFont {
-                        font: assets.button_font.clone(),
-                        font_size: 100.0,
-                        ..default()
-                    },
-                    TextColor(Color::srgb(1.0, 1.0, 1.0)),
+                    theme.text("Piping Hot", TextRole::Title),
                     TextShadow::default(),
                 )]
             ));
-            cmd.spawn(button("Play", &assets))
+            cmd.spawn(button("Play", &theme))
                 .insert(MenuAction::StartGame);
-            cmd.spawn(button("Options", &assets))
+            cmd.spawn(button("Options", &theme))
+                .insert(MenuAction::Options);
+            cmd.spawn(button("Credits", &theme))
                 .insert(MenuAction::StartGame);
-            cmd.spawn(button("Credits", &assets))
-                .insert(MenuAction::StartGame);
-            cmd.spawn(button("Quit", &assets))
+            cmd.spawn(button("Quit", &theme))
                 .insert(MenuAction::Quit);
         });
 }
 
-pub fn button(text: &str, assets: &UiAssets) -> impl Bundle + use<> {
-    (
-        Button,
-        Node {
-            width: Val::Px(250.0),
-            height: Val::Px(65.0),
-            border: UiRect::all(Val::Px(5.0)),
-            // horizontally center child text
-            justify_content: JustifyContent::Center,
-            // vertically center child text
-            align_items: AlignItems::Center,
-            ..default()
-        },
-        BorderColor(Color::BLACK),
-        BorderRadius::MAX,
-        // TODO: color palette asset
-        BackgroundColor(Color::srgb_u8(76, 146, 212)),
-        children![(
-            Text::new(text),
-            TextFont {
-                font: assets.button_font.clone(),
-                font_size: 33.0,
-                ..default()
-            },
-            TextColor(Color::srgb(0.9, 0.9, 0.9)),
-            TextShadow::default(),
-        )],
-    )
+pub fn button(text: &str, theme: &UiTheme) -> impl Bundle + use<> {
+    button_sized(text, Val::Px(250.0), theme)
+}
+
+pub fn button_small(text: &str, theme: &UiTheme) -> impl Bundle + use<> {
+    button_sized(text, Val::Px(100.0), theme)
+}
+
+pub fn button_wide(text: &str, theme: &UiTheme) -> impl Bundle + use<> {
+    button_sized(text, Val::Px(500.0), theme)
 }
-pub fn button_small(text: &str, assets: &UiAssets) -> impl Bundle + use<> {
+
+fn button_sized(text: &str, width: Val, theme: &UiTheme) -> impl Bundle + use<> {
+    let colors = &theme.get().button.normal;
     (
         Button,
+        ThemedButton,
+        TabIndex(0),
         Node {
-            width: Val::Px(100.0),
+            width,
             height: Val::Px(65.0),
-            border: UiRect::all(Val::Px(5.0)),
+            border: theme.border(),
             // horizontally center child text
             justify_content: JustifyContent::Center,
             // vertically center child text
             align_items: AlignItems::Center,
             ..default()
         },
-        BorderColor(Color::BLACK),
-        BorderRadius::MAX,
-        // TODO: color palette asset
-        BackgroundColor(Color::srgb_u8(76, 146, 212)),
-        children![(
-            Text::new(text),
-            TextFont {
-                font: assets.button_font.clone(),
-                font_size: 33.0,
-                ..default()
-            },
-            TextColor(Color::srgb(0.9, 0.9, 0.9)),
-            TextShadow::default(),
-        )],
+        BorderColor(colors.border.into()),
+        theme.border_radius(),
+        BackgroundColor(colors.background.into()),
+        children![(theme.text(text, TextRole::Button), TextShadow::default())],
     )
 }
 
+/// Colours buttons according to their interaction state, focus and the active theme.
 pub fn update_button_color(
-    mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor, &mut BorderColor),
-        (Changed<Interaction>, With<Button>),
+    theme: UiTheme,
+    focus: Res<InputFocus>,
+    mut sfx: EventWriter<PlaySfx>,
+    mut buttons: Query<
+        (
+            Entity,
+            Ref<Interaction>,
+            Has<Disabled>,
+            &mut BackgroundColor,
+            &mut BorderColor,
+            &Children,
+        ),
+        With<Button>,
     >,
+    mut texts: Query<&mut TextColor>,
 ) {
-    for (interaction, mut color, mut border_color) in interaction_query.iter_mut() {
-        match *interaction {
-            Interaction::Pressed => {
-                *color = Color::srgb_u8(200, 200, 200).into();
-                border_color.0 = Color::srgb_u8(200, 41, 13);
+    let palette = &theme.get().button;
+    for (entity, interaction, disabled, mut color, mut border_color, children) in &mut buttons {
+        if interaction.is_changed() && !interaction.is_added() && !disabled {
+            match *interaction {
+                Interaction::Pressed => {
+                    sfx.write(PlaySfx(Sfx::ButtonPress));
+                }
+                Interaction::Hovered => {
+                    sfx.write(PlaySfx(Sfx::ButtonHover));
+                }
+                Interaction::None => {}
             }
-            Interaction::Hovered => {
-                *color = Color::srgb_u8(176, 146, 112).into();
-                border_color.0 = Color::WHITE;
+        }
+
+        let colors = if disabled {
+            &palette.disabled
+        } else {
+            match *interaction {
+                Interaction::Pressed => &palette.pressed,
+                Interaction::Hovered => &palette.hovered,
+                Interaction::None if focus.0 == Some(entity) => &palette.focused,
+                Interaction::None => &palette.normal,
             }
-            Interaction::None => {
-                *color = Color::srgb_u8(76, 146, 212).into();
-                border_color.0 = Color::BLACK;
+        };
+        color.set_if_neq(BackgroundColor(colors.background.into()));
+        border_color.set_if_neq(BorderColor(colors.border.into()));
+        for child in children {
+            if let Ok(mut text_color) = texts.get_mut(*child) {
+                text_color.set_if_neq(TextColor(colors.text.into()));
             }
         }
     }
 }
+
 fn menu_action(
-    interaction_query: Query<(&Interaction, &MenuAction), (Changed<Interaction>, With<Button>)>,
+    interaction_query: Query<
+        (&Interaction, &MenuAction),
+        (Changed<Interaction>, With<Button>, Without<Disabled>),
+    >,
     mut app_exit_events: EventWriter<AppExit>,
     mut app_state: ResMut<NextState<AppState>>,
     mut load_level: EventWriter<LoadNextLevel>,
@@ -170,6 +181,9 @@ fn menu_action(
                     //load_level.write(LoadNextLevel("levels/map.tmx".into()));
                     app_state.set(AppState::LevelSelect);
                 }
+                MenuAction::Options => {
+                    app_state.set(AppState::Options);
+                }
                 MenuAction::Quit => {
                     app_exit_events.write(AppExit::Success);
                 }
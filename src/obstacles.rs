@@ -0,0 +1,156 @@
+//! Walls and decorative obstacles that block grid cells
+
+use crate::level::{GridShape, LevelGrid, TILE_SIZE};
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct ObstaclesPlugin;
+
+impl Plugin for ObstaclesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_obstacle_assets)
+            .add_systems(Update, spawn_obstacle_models);
+    }
+}
+
+/// Tile id for a cell painted as deliberately empty, loaded the same as a cell without a tile.
+pub const EMPTY_CELL_TILE: u32 = 64;
+
+/// Height of wall blocks above the floor.
+const WALL_HEIGHT: f32 = 1.2;
+
+/// Tile occupying a cell that never takes fluid and can't be moved, swapped or replaced.
+///
+/// Fluid running into an obstacle spills, the same as at the edge of the grid.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obstacle {
+    /// Solid block filling the whole cell, joining up with neighbouring walls (tile 65)
+    Wall,
+    /// Boulder, purely decorative (tile 66)
+    Rock,
+    /// Old machinery, purely decorative (tile 67)
+    Machinery,
+}
+
+impl Obstacle {
+    /// Obstacle placed by a map tile, if the tile is one.
+    pub fn from_tile(tile: u32) -> Option<Self> {
+        match tile {
+            65 => Some(Obstacle::Wall),
+            66 => Some(Obstacle::Rock),
+            67 => Some(Obstacle::Machinery),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+struct ObstacleAssets {
+    square_wall: Handle<Mesh>,
+    hex_wall: Handle<Mesh>,
+    rock: Handle<Mesh>,
+    machine_body: Handle<Mesh>,
+    machine_wheel: Handle<Mesh>,
+    stone: Handle<StandardMaterial>,
+    boulder: Handle<StandardMaterial>,
+    metal: Handle<StandardMaterial>,
+    paint: Handle<StandardMaterial>,
+}
+
+fn initialize_obstacle_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ObstacleAssets {
+        square_wall: meshes.add(Cuboid::new(TILE_SIZE, WALL_HEIGHT, TILE_SIZE)),
+        // Corners of the hexagon point along the Y axis, which ends up pointing across the rows
+        hex_wall: meshes.add(Extrusion::new(
+            RegularPolygon::new(TILE_SIZE / 3f32.sqrt(), 6),
+            WALL_HEIGHT,
+        )),
+        rock: meshes.add(Sphere::new(0.7).mesh().ico(1).unwrap()),
+        machine_body: meshes.add(Cuboid::new(1.3, 0.9, 1.0)),
+        machine_wheel: meshes.add(Cylinder::new(0.4, 0.15)),
+        stone: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.55, 0.5, 0.45),
+            perceptual_roughness: 0.95,
+            ..default()
+        }),
+        boulder: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.4, 0.4, 0.42),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        metal: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.35, 0.37, 0.4),
+            metallic: 0.9,
+            perceptual_roughness: 0.4,
+            ..default()
+        }),
+        paint: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.85, 0.6, 0.1),
+            perceptual_roughness: 0.6,
+            ..default()
+        }),
+    });
+}
+
+/// Builds the model of each newly spawned obstacle from simple meshes.
+fn spawn_obstacle_models(
+    mut commands: Commands,
+    obstacles: Query<(Entity, &Obstacle), Added<Obstacle>>,
+    grid: Option<Res<LevelGrid>>,
+    assets: Res<ObstacleAssets>,
+) {
+    let shape = grid.map_or(GridShape::Square, |grid| grid.shape);
+    for (entity, obstacle) in &obstacles {
+        commands
+            .entity(entity)
+            .with_children(|parent| match obstacle {
+                Obstacle::Wall => match shape {
+                    GridShape::Square => {
+                        parent.spawn((
+                            Mesh3d(assets.square_wall.clone()),
+                            MeshMaterial3d(assets.stone.clone()),
+                            Transform::from_xyz(0., WALL_HEIGHT / 2. - 0.5, 0.),
+                        ));
+                    }
+                    GridShape::Hex => {
+                        // The extrusion runs along Z, stand it up
+                        parent.spawn((
+                            Mesh3d(assets.hex_wall.clone()),
+                            MeshMaterial3d(assets.stone.clone()),
+                            Transform::from_xyz(0., WALL_HEIGHT / 2. - 0.5, 0.)
+                                .with_rotation(Quat::from_rotation_x(-FRAC_PI_2)),
+                        ));
+                    }
+                },
+                Obstacle::Rock => {
+                    parent.spawn((
+                        Mesh3d(assets.rock.clone()),
+                        MeshMaterial3d(assets.boulder.clone()),
+                        Transform::from_xyz(0.1, -0.1, -0.1).with_scale(Vec3::new(1.1, 0.7, 0.9)),
+                    ));
+                    parent.spawn((
+                        Mesh3d(assets.rock.clone()),
+                        MeshMaterial3d(assets.boulder.clone()),
+                        Transform::from_xyz(-0.5, -0.3, 0.5).with_scale(Vec3::splat(0.5)),
+                    ));
+                }
+                Obstacle::Machinery => {
+                    parent.spawn((
+                        Mesh3d(assets.machine_body.clone()),
+                        MeshMaterial3d(assets.paint.clone()),
+                        Transform::from_xyz(0., 0.1, 0.),
+                    ));
+                    parent.spawn((
+                        Mesh3d(assets.machine_wheel.clone()),
+                        MeshMaterial3d(assets.metal.clone()),
+                        Transform::from_xyz(0., 0.25, 0.55)
+                            .with_rotation(Quat::from_rotation_x(FRAC_PI_2)),
+                    ));
+                }
+            });
+    }
+}
@@ -0,0 +1,229 @@
+//! Options menu
+
+use crate::AppState;
+use crate::assets::UiAssets;
+use crate::menu::{self, Disabled, MenuItem};
+use crate::storage;
+use crate::theme::{ActiveTheme, TextRole, Theme, UiTheme};
+use bevy::input_focus::tab_navigation::TabGroup;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct OptionsPlugin;
+
+impl Plugin for OptionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(storage::load::<Settings>(SETTINGS_PATH))
+            .add_systems(OnEnter(AppState::Options), setup_options)
+            .add_systems(
+                Update,
+                (menu::update_button_color, options_action, update_labels)
+                    .chain()
+                    .run_if(in_state(AppState::Options)),
+            )
+            .add_systems(OnExit(AppState::Options), menu::teardown_menu);
+    }
+}
+
+/// File the settings are kept in, see [`storage`].
+const SETTINGS_PATH: &str = "settings.ron";
+
+/// Player preferences that aren't tied to a particular asset.
+///
+/// Saved whenever they change in the options menu, and read back on startup.
+#[derive(Resource, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub colorblind_mode: ColorblindMode,
+    /// Show a per-fluid icon on pipes, so fluids can be told apart without colour
+    pub fluid_icons: bool,
+    /// Volume applied to all audio, 0 to 1
+    pub master_volume: f32,
+    /// Volume of the music bus, 0 to 1
+    pub music_volume: f32,
+    /// Volume of the sound effect bus, 0 to 1
+    pub sfx_volume: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            colorblind_mode: ColorblindMode::default(),
+            fluid_icons: false,
+            master_volume: 1.0,
+            music_volume: 0.6,
+            sfx_volume: 0.8,
+        }
+    }
+}
+
+/// Volume change per press of a volume button; volumes wrap around from full to muted.
+const VOLUME_STEP: f32 = 0.2;
+
+fn next_volume(volume: f32) -> f32 {
+    if volume >= 1.0 - VOLUME_STEP / 2.0 {
+        0.0
+    } else {
+        (volume + VOLUME_STEP).min(1.0)
+    }
+}
+
+/// Colour vision deficiency the fluid palette is adjusted for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorblindMode {
+    #[default]
+    Off,
+    Deuteranopia,
+    Protanopia,
+    Tritanopia,
+}
+
+impl ColorblindMode {
+    fn next(self) -> Self {
+        match self {
+            ColorblindMode::Off => ColorblindMode::Deuteranopia,
+            ColorblindMode::Deuteranopia => ColorblindMode::Protanopia,
+            ColorblindMode::Protanopia => ColorblindMode::Tritanopia,
+            ColorblindMode::Tritanopia => ColorblindMode::Off,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+enum OptionsAction {
+    CycleTheme,
+    CycleColorblindMode,
+    ToggleFluidIcons,
+    CycleMasterVolume,
+    CycleMusicVolume,
+    CycleSfxVolume,
+    Back,
+}
+
+fn setup_options(mut commands: Commands, theme: UiTheme) {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                row_gap: Val::Px(10.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            MenuItem,
+            TabGroup::default(),
+        ))
+        .with_children(|cmd| {
+            cmd.spawn((
+                theme.text("Options", TextRole::Title),
+                TextShadow::default(),
+            ));
+            cmd.spawn(menu::button_wide("Theme", &theme))
+                .insert(OptionsAction::CycleTheme);
+            cmd.spawn(menu::button_wide("Colour mode", &theme))
+                .insert(OptionsAction::CycleColorblindMode);
+            cmd.spawn(menu::button_wide("Fluid icons", &theme))
+                .insert(OptionsAction::ToggleFluidIcons);
+            cmd.spawn(menu::button_wide("Master volume", &theme))
+                .insert(OptionsAction::CycleMasterVolume);
+            cmd.spawn(menu::button_wide("Music volume", &theme))
+                .insert(OptionsAction::CycleMusicVolume);
+            cmd.spawn(menu::button_wide("Effects volume", &theme))
+                .insert(OptionsAction::CycleSfxVolume);
+            cmd.spawn(menu::button("Back", &theme))
+                .insert(OptionsAction::Back);
+            cmd.spawn(theme.text("Tab / Shift+Tab to move between buttons", TextRole::Body));
+        });
+}
+
+fn options_action(
+    interaction_query: Query<
+        (&Interaction, &OptionsAction),
+        (Changed<Interaction>, With<Button>, Without<Disabled>),
+    >,
+    mut app_state: ResMut<NextState<AppState>>,
+    mut active_theme: ResMut<ActiveTheme>,
+    mut settings: ResMut<Settings>,
+    assets: Res<UiAssets>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            match action {
+                OptionsAction::CycleTheme => {
+                    let themes = assets.themes();
+                    let current = themes
+                        .iter()
+                        .position(|handle| **handle == active_theme.0)
+                        .unwrap_or(0);
+                    active_theme.0 = themes[(current + 1) % themes.len()].clone();
+                }
+                OptionsAction::CycleColorblindMode => {
+                    settings.colorblind_mode = settings.colorblind_mode.next();
+                }
+                OptionsAction::ToggleFluidIcons => {
+                    settings.fluid_icons = !settings.fluid_icons;
+                }
+                OptionsAction::CycleMasterVolume => {
+                    settings.master_volume = next_volume(settings.master_volume);
+                }
+                OptionsAction::CycleMusicVolume => {
+                    settings.music_volume = next_volume(settings.music_volume);
+                }
+                OptionsAction::CycleSfxVolume => {
+                    settings.sfx_volume = next_volume(settings.sfx_volume);
+                }
+                OptionsAction::Back => {
+                    app_state.set(AppState::MainMenu);
+                }
+            }
+            if settings.is_changed() {
+                storage::save(SETTINGS_PATH, &*settings);
+            }
+        }
+    }
+}
+
+/// Keeps the option button captions in sync with the current settings.
+fn update_labels(
+    buttons: Query<(&OptionsAction, &Children)>,
+    mut texts: Query<&mut Text>,
+    active_theme: Res<ActiveTheme>,
+    themes: Res<Assets<Theme>>,
+    settings: Res<Settings>,
+) {
+    for (action, children) in &buttons {
+        let label = match action {
+            OptionsAction::CycleTheme => {
+                let name = themes
+                    .get(&active_theme.0)
+                    .map(|theme| theme.name.as_str())
+                    .unwrap_or("?");
+                format!("Theme: {name}")
+            }
+            OptionsAction::CycleColorblindMode => {
+                format!("Colour mode: {:?}", settings.colorblind_mode)
+            }
+            OptionsAction::ToggleFluidIcons => {
+                let state = if settings.fluid_icons { "On" } else { "Off" };
+                format!("Fluid icons: {state}")
+            }
+            OptionsAction::CycleMasterVolume => {
+                format!("Master: {:.0}%", settings.master_volume * 100.0)
+            }
+            OptionsAction::CycleMusicVolume => {
+                format!("Music: {:.0}%", settings.music_volume * 100.0)
+            }
+            OptionsAction::CycleSfxVolume => {
+                format!("Effects: {:.0}%", settings.sfx_volume * 100.0)
+            }
+            OptionsAction::Back => continue,
+        };
+        for child in children {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.set_if_neq(Text(label.clone()));
+            }
+        }
+    }
+}
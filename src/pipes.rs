@@ -1,38 +1,95 @@
 //! Pipe definitions
 
+use crate::options::{ColorblindMode, Settings};
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3};
+use thiserror::Error;
 
-type SlotId = u8;
+pub type SlotId = u8;
 
-type FluidId = String;
+pub type FluidId = String;
+
+/// Number of sides of a tile on a square grid.
+pub const SIDES: SlotId = 4;
+
+/// Number of sides of a tile on a hexagonal grid.
+pub const HEX_SIDES: SlotId = 6;
+
+/// Internal routing slot where a spring's fluid wells up.
+pub const INTERNAL_SOURCE: SlotId = 100;
+
+/// Internal routing slot where fluid drains away.
+pub const INTERNAL_SINK: SlotId = 101;
+
+/// Internal routing slot where fluid passes over to the paired portal.
+pub const PORTAL: SlotId = 102;
+
+/// Temperature that fluids cool down towards as they run through the pipes, °C.
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+
+/// Share of the difference to the ambient temperature that fluid loses per pipe.
+pub const COOLING_PER_PIPE: f32 = 0.1;
+
+/// Fluid at or above this temperature glows and steams, °C.
+pub const HOT_TEMPERATURE: f32 = 60.0;
+
+/// Fluid at or below this temperature frosts over, °C.
+pub const COLD_TEMPERATURE: f32 = 5.0;
+
+/// How far the upper channel of a bridge runs above the pipe axis.
+pub const BRIDGE_HEIGHT: f32 = 0.8;
 
 pub struct PipePlugin;
 
 #[derive(Resource, Debug, DerefMut, Deref)]
 pub struct PipeArchetypes(HashMap<u32, Pipe>);
 
+#[derive(Resource, Debug, DerefMut, Deref)]
+pub struct LargePipeArchetypes(HashMap<u32, LargePipe>);
+
 impl Plugin for PipePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, initialize_pipe_archetypes);
+        app.add_systems(
+            Startup,
+            (
+                initialize_pipe_archetypes,
+                initialize_large_pipe_archetypes,
+                initialize_fluids,
+                initialize_valve_arrow,
+                initialize_hex_meshes,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                spawn_fluid_icons,
+                spawn_valve_arrows,
+                spawn_bridge_spans,
+                spawn_hex_arms,
+                apply_fluid_palette.run_if(resource_changed::<Settings>),
+                toggle_fluid_icons.run_if(resource_changed::<Settings>),
+            ),
+        );
     }
 }
 
 fn initialize_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetServer>) {
     let mut pipes = PipeArchetypes(HashMap::new());
+    let base = Pipe::default();
+    let model =
+        |scene| asset_server.load(GltfAssetLabel::Scene(scene).from_asset("models/pipe.glb"));
+    use Slot::{Bidirectional as B, None as N};
 
     // Input
     pipes.insert(
         16,
         Pipe {
+            name: "input",
             source: Some("water".into()),
-            sink: None,
-            slots: [Slot::Output, Slot::None, Slot::None, Slot::None],
-            progress: 0.0,
-            progress_rate: 1.0,
-            internal_routing: vec![],
-            model: asset_server.load(GltfAssetLabel::Scene(6).from_asset("models/pipe.glb")),
-            locked: false,
+            slots: vec![Slot::Output, N, N, N],
+            model: model(6),
+            ..base.clone()
         },
     );
 
@@ -40,54 +97,33 @@ fn initialize_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetSer
     pipes.insert(
         32,
         Pipe {
-            source: None,
+            name: "output",
             sink: Some("water".into()),
-            slots: [Slot::Input, Slot::None, Slot::None, Slot::None],
-            progress: 0.0,
-            progress_rate: 1.0,
-            internal_routing: vec![],
-            model: asset_server.load(GltfAssetLabel::Scene(6).from_asset("models/pipe.glb")),
-            locked: false,
+            slots: vec![Slot::Input, N, N, N],
+            model: model(6),
+            ..base.clone()
         },
     );
 
     // Straight pipe
-    pipes.insert(
-        0,
-        Pipe {
-            source: None,
-            sink: None,
-            slots: [
-                Slot::Bidirectional,
-                Slot::None,
-                Slot::Bidirectional,
-                Slot::None,
-            ],
-            progress: 0.0,
-            progress_rate: 1.0,
-            internal_routing: vec![InternalRouting::passthrough(0, 2)],
-            model: asset_server.load(GltfAssetLabel::Scene(0).from_asset("models/pipe.glb")),
-            locked: false,
-        },
-    );
+    let straight = Pipe {
+        name: "straight",
+        slots: vec![B, N, B, N],
+        internal_routing: vec![InternalRouting::passthrough(0, 2)],
+        model: model(0),
+        ..base.clone()
+    };
+    pipes.insert(0, straight.clone());
 
     // Curved pipe
     pipes.insert(
         1,
         Pipe {
-            source: None,
-            sink: None,
-            slots: [
-                Slot::Bidirectional,
-                Slot::Bidirectional,
-                Slot::None,
-                Slot::None,
-            ],
-            progress: 0.0,
-            progress_rate: 1.0,
+            name: "curve",
+            slots: vec![B, B, N, N],
             internal_routing: vec![InternalRouting::passthrough(0, 1)],
-            model: asset_server.load(GltfAssetLabel::Scene(1).from_asset("models/pipe.glb")),
-            locked: false,
+            model: model(1),
+            ..base.clone()
         },
     );
 
@@ -95,14 +131,11 @@ fn initialize_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetSer
     pipes.insert(
         2,
         Pipe {
-            source: None,
-            sink: None,
-            slots: [Slot::Bidirectional, Slot::None, Slot::None, Slot::None],
-            progress: 0.0,
-            progress_rate: 1.0,
+            name: "cork",
+            slots: vec![B, N, N, N],
             internal_routing: vec![InternalRouting::passthrough(0, 5)],
-            model: asset_server.load(GltfAssetLabel::Scene(2).from_asset("models/pipe.glb")),
-            locked: false,
+            model: model(2),
+            ..base.clone()
         },
     );
 
@@ -110,16 +143,8 @@ fn initialize_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetSer
     pipes.insert(
         3,
         Pipe {
-            source: None,
-            sink: None,
-            slots: [
-                Slot::Bidirectional,
-                Slot::Bidirectional,
-                Slot::Bidirectional,
-                Slot::None,
-            ],
-            progress: 0.0,
-            progress_rate: 1.0,
+            name: "T",
+            slots: vec![B, B, B, N],
             internal_routing: vec![
                 InternalRouting::mix(0, 5),
                 InternalRouting::mix(1, 5),
@@ -128,22 +153,733 @@ fn initialize_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetSer
                 InternalRouting::passthrough(5, 1),
                 InternalRouting::passthrough(5, 2),
             ],
-            model: asset_server.load(GltfAssetLabel::Scene(3).from_asset("models/pipe.glb")),
-            locked: false,
+            model: model(3),
+            ..base.clone()
+        },
+    );
+
+    // Check valve, straight: fluid only passes from side 0 to side 2
+    pipes.insert(
+        8,
+        Pipe {
+            name: "check valve",
+            slots: vec![Slot::Input, N, Slot::Output, N],
+            ..straight.clone()
+        },
+    );
+
+    // Check valve, curved: fluid only passes from side 0 to side 1
+    pipes.insert(
+        9,
+        Pipe {
+            name: "check valve",
+            slots: vec![Slot::Input, Slot::Output, N, N],
+            internal_routing: vec![InternalRouting::passthrough(0, 1)],
+            model: model(1),
+            ..base.clone()
+        },
+    );
+
+    // Gate valve: a straight pipe the player opens and closes
+    pipes.insert(
+        10,
+        Pipe {
+            name: "gate",
+            valve: Some(Valve::Gate { open: false }),
+            ..straight.clone()
+        },
+    );
+
+    // Diverter: a T pipe sending the fluid from the stem to one selectable end
+    pipes.insert(
+        11,
+        Pipe {
+            name: "diverter",
+            slots: vec![B, B, B, N],
+            internal_routing: vec![InternalRouting::passthrough(1, 0)],
+            model: model(3),
+            valve: Some(Valve::Diverter {
+                inlet: 1,
+                outlets: [0, 2],
+                selected: 0,
+            }),
+            ..base.clone()
+        },
+    );
+
+    // Crossover: two straight channels crossing without mixing
+    let crossover = Pipe {
+        name: "crossover",
+        slots: vec![B, B, B, B],
+        internal_routing: vec![
+            InternalRouting::passthrough(0, 2),
+            InternalRouting::passthrough(1, 3),
+        ],
+        model: model(4),
+        ..base.clone()
+    };
+    pipes.insert(4, crossover.clone());
+
+    // Bridge: a straight pipe with a second one passing over it
+    pipes.insert(
+        5,
+        Pipe {
+            name: "bridge",
+            model: model(0),
+            bridge: true,
+            ..crossover
+        },
+    );
+
+    // Pump: a straight pipe speeding up the fluid through it and everywhere downstream
+    pipes.insert(
+        7,
+        Pipe {
+            name: "pump",
+            pump: 2.0,
+            ..straight.clone()
+        },
+    );
+
+    // Narrow pipe: a straight pipe the fluid takes twice as long to get through
+    pipes.insert(
+        14,
+        Pipe {
+            name: "narrow",
+            progress_rate: 0.5,
+            ..straight.clone()
+        },
+    );
+
+    // Portal: fluid going in comes out of the paired portal
+    pipes.insert(
+        25,
+        Pipe {
+            name: "portal",
+            slots: vec![B, N, N, N],
+            internal_routing: vec![InternalRouting::passthrough(0, PORTAL)],
+            model: model(6),
+            portal: Some(Portal::default()),
+            ..base.clone()
+        },
+    );
+
+    // Tank: a straight pipe that fills a container before the fluid flows on
+    pipes.insert(
+        6,
+        Pipe {
+            name: "tank",
+            internal_routing: vec![
+                InternalRouting::passthrough(0, 4),
+                InternalRouting::passthrough(4, 2),
+            ],
+            containers: vec![Container {
+                slot: 4,
+                capacity: 3.0,
+                volume: 0.0,
+                mixed: Vec::new(),
+            }],
+            model: model(5),
+            ..straight.clone()
+        },
+    );
+
+    // Spring: water welling up inside the tile
+    pipes.insert(
+        24,
+        Pipe {
+            name: "spring",
+            slots: vec![Slot::Output, N, N, N],
+            internal_routing: vec![InternalRouting::passthrough(INTERNAL_SOURCE, 0)],
+            model: model(2),
+            spring: Some(Spring {
+                fluid: "water".into(),
+                rate: 0.5,
+                capacity: 4.0,
+                produced: 0.0,
+            }),
+            ..base.clone()
+        },
+    );
+
+    // Drain: takes in fluid until it is full
+    pipes.insert(
+        46,
+        Pipe {
+            name: "drain",
+            slots: vec![Slot::Input, N, N, N],
+            internal_routing: vec![InternalRouting::passthrough(0, INTERNAL_SINK)],
+            model: model(2),
+            drain: Some(Drain {
+                rate: 0.5,
+                capacity: 5.0,
+                drained: 0.0,
+            }),
+            ..base.clone()
+        },
+    );
+
+    // Piping hot output: only takes water hot enough to steam
+    pipes.insert(
+        47,
+        Pipe {
+            name: "hot output",
+            sink: Some("water".into()),
+            slots: vec![Slot::Input, N, N, N],
+            model: model(6),
+            sink_temperature: Some(TemperatureRange {
+                min: HOT_TEMPERATURE,
+                max: 100.0,
+            }),
+            ..base.clone()
+        },
+    );
+
+    // Heater: a straight pipe warming the fluid passing through
+    pipes.insert(
+        12,
+        Pipe {
+            name: "heater",
+            heating: 30.0,
+            ..straight.clone()
+        },
+    );
+
+    // Cooler: a straight pipe chilling the fluid passing through
+    pipes.insert(
+        13,
+        Pipe {
+            name: "cooler",
+            heating: -30.0,
+            ..straight
+        },
+    );
+
+    // Hex pipes, for hexagonal grids; they are built from meshes, see `spawn_hex_arms`
+    let hex = |name, slots: [Slot; 6], internal_routing: Vec<InternalRouting>| Pipe {
+        name,
+        slots: slots.into(),
+        internal_routing,
+        ..base.clone()
+    };
+
+    // Hex straight pipe
+    pipes.insert(
+        40,
+        hex(
+            "straight",
+            [B, N, N, B, N, N],
+            vec![InternalRouting::passthrough(0, 3)],
+        ),
+    );
+
+    // Hex wide bend, turning by one side
+    pipes.insert(
+        41,
+        hex(
+            "wide bend",
+            [B, N, B, N, N, N],
+            vec![InternalRouting::passthrough(0, 2)],
+        ),
+    );
+
+    // Hex sharp bend, between neighbouring sides
+    pipes.insert(
+        42,
+        hex(
+            "sharp bend",
+            [B, B, N, N, N, N],
+            vec![InternalRouting::passthrough(0, 1)],
+        ),
+    );
+
+    // Hex Y junction, through the hub at slot 6
+    pipes.insert(
+        43,
+        hex(
+            "Y",
+            [B, N, B, N, B, N],
+            vec![
+                InternalRouting::passthrough(0, 6),
+                InternalRouting::passthrough(2, 6),
+                InternalRouting::passthrough(4, 6),
+            ],
+        ),
+    );
+
+    // Hex input
+    pipes.insert(
+        44,
+        Pipe {
+            source: Some("water".into()),
+            ..hex("input", [Slot::Output, N, N, N, N, N], vec![])
         },
     );
 
+    // Hex output
+    pipes.insert(
+        45,
+        Pipe {
+            sink: Some("water".into()),
+            ..hex("output", [Slot::Input, N, N, N, N, N], vec![])
+        },
+    );
+
+    pipes.retain(|id, pipe| match pipe.validate() {
+        Ok(()) => true,
+        Err(err) => {
+            error!("Pipe archetype {} has invalid routing: {}", id, err);
+            false
+        }
+    });
+
+    commands.insert_resource(pipes);
+}
+
+fn initialize_large_pipe_archetypes(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut pipes = LargePipeArchetypes(HashMap::new());
+
+    // Parts only differ in their slots, routing and containers; the piece has the model
+    let base = Pipe::default();
+    let part = |slots: [Slot; 4], internal_routing: Vec<InternalRouting>| Pipe {
+        slots: slots.into(),
+        internal_routing,
+        locked: true,
+        ..base.clone()
+    };
+    let straight = || {
+        part(
+            [
+                Slot::Bidirectional,
+                Slot::None,
+                Slot::Bidirectional,
+                Slot::None,
+            ],
+            vec![InternalRouting::passthrough(0, 2)],
+        )
+    };
+
+    // Heat exchanger: two straight channels side by side, evening out their temperatures
+    pipes.insert(
+        56,
+        LargePipe {
+            parts: vec![
+                (IVec2::new(0, 0), straight()),
+                (IVec2::new(1, 0), straight()),
+            ],
+            model: asset_server.load(GltfAssetLabel::Scene(5).from_asset("models/pipe.glb")),
+            model_scale: Vec3::new(1.0, 1.0, 2.0),
+            exchanges_heat: true,
+        },
+    );
+
+    // Mixer: two inlets on top running into a mixing chamber, which lets out at the bottom left
+    // once the fluids of both inlets are in
+    let chamber = Pipe {
+        containers: vec![Container {
+            slot: 5,
+            capacity: 2.0,
+            volume: 0.0,
+            mixed: Vec::new(),
+        }],
+        ..part(
+            [Slot::Input, Slot::Input, Slot::Output, Slot::None],
+            vec![
+                InternalRouting::mix(0, 5),
+                InternalRouting::mix(1, 5),
+                InternalRouting::passthrough(5, 2),
+            ],
+        )
+    };
+    let inlet = || {
+        part(
+            [Slot::Input, Slot::None, Slot::Output, Slot::None],
+            vec![InternalRouting::passthrough(0, 2)],
+        )
+    };
+    pipes.insert(
+        57,
+        LargePipe {
+            parts: vec![
+                (IVec2::new(0, 0), inlet()),
+                (IVec2::new(1, 0), inlet()),
+                (IVec2::new(0, 1), chamber),
+                (
+                    IVec2::new(1, 1),
+                    part(
+                        [Slot::Input, Slot::None, Slot::None, Slot::Output],
+                        vec![InternalRouting::passthrough(0, 3)],
+                    ),
+                ),
+            ],
+            model: asset_server.load(GltfAssetLabel::Scene(4).from_asset("models/pipe.glb")),
+            model_scale: Vec3::new(2.0, 1.0, 2.0),
+            exchanges_heat: true,
+        },
+    );
+
+    pipes.retain(|id, pipe| {
+        let invalid = pipe
+            .parts
+            .iter()
+            .find_map(|(offset, part)| part.validate().err().map(|err| (offset, err)));
+        match invalid {
+            None => true,
+            Some((offset, err)) => {
+                error!(
+                    "Large pipe archetype {} has invalid routing in part {}: {}",
+                    id, offset, err
+                );
+                false
+            }
+        }
+    });
+
     commands.insert_resource(pipes);
 }
 
 pub struct Fluid {
     pub id: FluidId,
+    /// Temperature the fluid leaves its sources at, °C
+    pub temperature: f32,
+    /// How much slower than water the fluid flows
+    pub viscosity: f32,
     pub material: Handle<StandardMaterial>,
+    /// Glowing material for fluid at or above [`HOT_TEMPERATURE`]
+    pub hot_material: Handle<StandardMaterial>,
+    /// Frosted material for fluid at or below [`COLD_TEMPERATURE`]
+    pub cold_material: Handle<StandardMaterial>,
+    pub palette: FluidPalette,
+    /// Shape shown on pipes carrying this fluid, so it never has to be told apart by colour alone
+    pub icon: Handle<Mesh>,
 }
 
-#[derive(Resource)]
+/// Fluid colour for each colour vision mode.
+#[derive(Debug, Clone)]
+pub struct FluidPalette {
+    pub normal: Color,
+    pub deuteranopia: Color,
+    pub protanopia: Color,
+    pub tritanopia: Color,
+}
+
+impl Fluid {
+    /// Material showing the fluid at the given temperature.
+    pub fn material(&self, temperature: f32) -> &Handle<StandardMaterial> {
+        if temperature >= HOT_TEMPERATURE {
+            &self.hot_material
+        } else if temperature <= COLD_TEMPERATURE {
+            &self.cold_material
+        } else {
+            &self.material
+        }
+    }
+}
+
+/// Frosted fluid is washed out towards white.
+fn frosted(color: Color) -> Color {
+    color.mix(&Color::WHITE, 0.5)
+}
+
+impl FluidPalette {
+    pub fn color(&self, mode: ColorblindMode) -> Color {
+        match mode {
+            ColorblindMode::Off => self.normal,
+            ColorblindMode::Deuteranopia => self.deuteranopia,
+            ColorblindMode::Protanopia => self.protanopia,
+            ColorblindMode::Tritanopia => self.tritanopia,
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
 pub struct Fluids(HashMap<FluidId, Fluid>);
 
+fn initialize_fluids(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let mut fluids = Fluids(HashMap::new());
+    let mut add =
+        |id: &str, temperature: f32, viscosity: f32, palette: FluidPalette, icon: Mesh| {
+            fluids.0.insert(
+                id.into(),
+                Fluid {
+                    id: id.into(),
+                    temperature,
+                    viscosity,
+                    material: materials.add(StandardMaterial {
+                        base_color: palette.normal,
+                        perceptual_roughness: 0.2,
+                        ..default()
+                    }),
+                    hot_material: materials.add(StandardMaterial {
+                        base_color: palette.normal,
+                        emissive: LinearRgba::rgb(1.5, 0.5, 0.1),
+                        perceptual_roughness: 0.2,
+                        ..default()
+                    }),
+                    cold_material: materials.add(StandardMaterial {
+                        base_color: frosted(palette.normal),
+                        perceptual_roughness: 0.7,
+                        ..default()
+                    }),
+                    palette,
+                    icon: meshes.add(icon),
+                },
+            );
+        };
+
+    // Alternate palettes use the Okabe-Ito colours, which stay distinct for the given deficiency.
+    add(
+        "water",
+        20.0,
+        1.0,
+        FluidPalette {
+            normal: Color::srgb_u8(40, 110, 230),
+            deuteranopia: Color::srgb_u8(0, 114, 178),
+            protanopia: Color::srgb_u8(0, 114, 178),
+            tritanopia: Color::srgb_u8(0, 158, 115),
+        },
+        Circle::new(0.3).mesh().build(),
+    );
+    add(
+        "lava",
+        1000.0,
+        3.0,
+        FluidPalette {
+            normal: Color::srgb_u8(220, 40, 20),
+            deuteranopia: Color::srgb_u8(230, 159, 0),
+            protanopia: Color::srgb_u8(240, 228, 66),
+            tritanopia: Color::srgb_u8(213, 94, 0),
+        },
+        RegularPolygon::new(0.35, 3).mesh().build(),
+    );
+    add(
+        "acid",
+        20.0,
+        0.8,
+        FluidPalette {
+            normal: Color::srgb_u8(60, 200, 60),
+            deuteranopia: Color::srgb_u8(86, 180, 233),
+            protanopia: Color::srgb_u8(86, 180, 233),
+            tritanopia: Color::srgb_u8(240, 228, 66),
+        },
+        Rectangle::new(0.5, 0.5).mesh().build(),
+    );
+    add(
+        "goo",
+        20.0,
+        2.0,
+        FluidPalette {
+            normal: Color::srgb_u8(140, 60, 200),
+            deuteranopia: Color::srgb_u8(204, 121, 167),
+            protanopia: Color::srgb_u8(204, 121, 167),
+            tritanopia: Color::srgb_u8(230, 230, 230),
+        },
+        Rhombus::new(0.6, 0.6).mesh().build(),
+    );
+
+    commands.insert_resource(fluids);
+}
+
+/// Recolours all fluid materials for the selected colour vision mode.
+fn apply_fluid_palette(
+    fluids: Res<Fluids>,
+    settings: Res<Settings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for fluid in fluids.values() {
+        let color = fluid.palette.color(settings.colorblind_mode);
+        for (handle, color) in [
+            (&fluid.material, color),
+            (&fluid.hot_material, color),
+            (&fluid.cold_material, frosted(color)),
+        ] {
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color = color;
+            }
+        }
+    }
+}
+
+/// Marker for the fluid icon floating above a pipe.
+#[derive(Component, Debug)]
+pub struct FluidIcon;
+
+fn fluid_icon_visibility(settings: &Settings) -> Visibility {
+    if settings.fluid_icons {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    }
+}
+
+/// Adds an icon for the fluid of each newly spawned source, sink or spring.
+fn spawn_fluid_icons(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    fluids: Res<Fluids>,
+    settings: Res<Settings>,
+) {
+    for (entity, pipe) in &pipes {
+        let Some(fluid) = pipe
+            .source
+            .as_ref()
+            .or(pipe.sink.as_ref())
+            .or(pipe.spring.as_ref().map(|spring| &spring.fluid))
+            .and_then(|id| fluids.get(id))
+        else {
+            continue;
+        };
+        commands
+            .entity(entity)
+            .with_child(fluid_icon(fluid, &settings, Vec3::ZERO));
+    }
+}
+
+/// Icon for a fluid above the pipe center, moved by `offset` in the pipe model space.
+pub fn fluid_icon(fluid: &Fluid, settings: &Settings, offset: Vec3) -> impl Bundle + use<> {
+    (
+        FluidIcon,
+        Mesh3d(fluid.icon.clone()),
+        MeshMaterial3d(fluid.material.clone()),
+        Transform::from_translation(Vec3::new(0., 1.2, 0.) + offset)
+            .with_rotation(Quat::from_rotation_x(-FRAC_PI_2)),
+        fluid_icon_visibility(settings),
+    )
+}
+
+fn toggle_fluid_icons(settings: Res<Settings>, mut icons: Query<&mut Visibility, With<FluidIcon>>) {
+    for mut visibility in &mut icons {
+        visibility.set_if_neq(fluid_icon_visibility(&settings));
+    }
+}
+
+/// Arrow shown on check valves, pointing the way the fluid may flow.
+#[derive(Resource, Debug)]
+struct ValveArrow {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn initialize_valve_arrow(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ValveArrow {
+        mesh: meshes.add(Triangle2d::new(
+            Vec2::new(0., 0.4),
+            Vec2::new(-0.3, -0.2),
+            Vec2::new(0.3, -0.2),
+        )),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgb(1.0, 0.85, 0.1),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Adds a direction arrow to each newly spawned check valve.
+fn spawn_valve_arrows(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    arrow: Res<ValveArrow>,
+) {
+    for (entity, pipe) in &pipes {
+        if pipe.source.is_some() || pipe.sink.is_some() {
+            continue;
+        }
+        let Some(output) =
+            (0..pipe.sides()).find(|side| matches!(pipe.slots[*side as usize], Slot::Output))
+        else {
+            continue;
+        };
+        // The triangle points along -Z once laid flat, turn it towards the output side
+        let direction = model_side_direction(output, pipe.sides());
+        let rotation =
+            Quat::from_rotation_arc(Vec3::NEG_Z, direction) * Quat::from_rotation_x(-FRAC_PI_2);
+        commands.entity(entity).with_child((
+            Mesh3d(arrow.mesh.clone()),
+            MeshMaterial3d(arrow.material.clone()),
+            Transform::from_xyz(0., 1.0, 0.).with_rotation(rotation),
+        ));
+    }
+}
+
+/// Meshes hex pipes are built from, lacking models of their own.
+#[derive(Resource, Debug)]
+struct HexMeshes {
+    arm: Handle<Mesh>,
+    hub: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn initialize_hex_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(HexMeshes {
+        arm: meshes.add(Cylinder::new(0.35, 1.0)),
+        hub: meshes.add(Sphere::new(0.45)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.6, 0.62, 0.65),
+            metallic: 0.8,
+            perceptual_roughness: 0.35,
+            ..default()
+        }),
+    });
+}
+
+/// Builds each newly spawned hex pipe from a hub and an arm out to every open side.
+fn spawn_hex_arms(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    hex: Res<HexMeshes>,
+) {
+    for (entity, pipe) in &pipes {
+        if pipe.sides() != HEX_SIDES {
+            continue;
+        }
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                Mesh3d(hex.hub.clone()),
+                MeshMaterial3d(hex.material.clone()),
+            ));
+            for side in 0..pipe.sides() {
+                if matches!(pipe.slots[side as usize], Slot::None) {
+                    continue;
+                }
+                let direction = model_side_direction(side, pipe.sides());
+                parent.spawn((
+                    Mesh3d(hex.arm.clone()),
+                    MeshMaterial3d(hex.material.clone()),
+                    Transform::from_translation(direction * 0.5)
+                        .with_rotation(Quat::from_rotation_arc(Vec3::Y, direction)),
+                ));
+            }
+        });
+    }
+}
+
+/// Adds the upper span, running from side 1 to side 3, to each newly spawned bridge.
+fn spawn_bridge_spans(mut commands: Commands, pipes: Query<(Entity, &Pipe), Added<Pipe>>) {
+    for (entity, pipe) in &pipes {
+        if !pipe.bridge {
+            continue;
+        }
+        commands.entity(entity).with_child((
+            SceneRoot(pipe.model.clone()),
+            Transform::from_xyz(0., BRIDGE_HEIGHT, 0.)
+                .with_rotation(Quat::from_rotation_y(FRAC_PI_2)),
+        ));
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub enum Slot {
     #[default]
@@ -153,26 +889,281 @@ pub enum Slot {
     Bidirectional,
 }
 
+impl Slot {
+    pub fn accepts_input(&self) -> bool {
+        matches!(self, Slot::Input | Slot::Bidirectional)
+    }
+
+    pub fn emits_output(&self) -> bool {
+        matches!(self, Slot::Output | Slot::Bidirectional)
+    }
+}
+
+/// Side of the neighbouring tile that faces the given side, on a grid of tiles with `sides`
+/// sides.
+pub fn opposite_side(side: SlotId, sides: SlotId) -> SlotId {
+    (side + sides / 2) % sides
+}
+
+/// Fluids that make a third one when they mix, in either order.
+const MIXTURES: [(&str, &str, &str); 1] = [("water", "lava", "goo")];
+
+/// Fluid that two fluids make when they mix, `None` if they don't mix.
+///
+/// A fluid mixes with itself into more of the same.
+pub fn mixture(a: &FluidId, b: &FluidId) -> Option<FluidId> {
+    if a == b {
+        return Some(a.clone());
+    }
+    MIXTURES
+        .iter()
+        .find(|(x, y, _)| (a == x && b == y) || (a == y && b == x))
+        .map(|(_, _, mixed)| mixed.to_string())
+}
+
+/// Direction of a local side in the model space of a pipe with `sides` sides.
+///
+/// The models of `models/pipe.glb` have side 0 along -X, so [`rotation_quat`] turns them a quarter
+/// to put it at the top. Hex pipes are built in world space, with side 0 at the top right.
+pub fn model_side_direction(side: SlotId, sides: SlotId) -> Vec3 {
+    if sides == HEX_SIDES {
+        let direction = hex_side_direction(side);
+        return Vec3::new(direction.x, 0., direction.y);
+    }
+    match side % SIDES {
+        0 => Vec3::NEG_X,
+        1 => Vec3::NEG_Z,
+        2 => Vec3::X,
+        _ => Vec3::Z,
+    }
+}
+
+/// Direction on the XZ plane of a side of a hex tile, clockwise from the top right.
+pub fn hex_side_direction(side: SlotId) -> Vec2 {
+    Vec2::from_angle(-FRAC_PI_3 + FRAC_PI_3 * (side % HEX_SIDES) as f32)
+}
+
+/// World rotation of a pipe model with `sides` sides turned `rotation` steps clockwise.
+pub fn rotation_quat(rotation: u8, sides: SlotId) -> Quat {
+    if sides == HEX_SIDES {
+        return Quat::from_rotation_y(-FRAC_PI_3 * rotation as f32);
+    }
+    Quat::from_rotation_y(-FRAC_PI_2 * (rotation as f32 + 1.))
+}
+
+/// Adds the visuals of a pipe to its entity: its model, or for pipes built from meshes, like hex
+/// pipes, the visibility their meshes inherit once they are added.
+pub fn spawn_pipe_visual(entity: &mut EntityCommands, pipe: &Pipe) {
+    if pipe.model != Handle::default() {
+        entity.insert(SceneRoot(pipe.model.clone()));
+    } else {
+        entity.insert(Visibility::default());
+    }
+}
+
 #[derive(Component, Debug, Clone)]
 pub struct Pipe {
+    /// Name shown to the player, like in the queue preview
+    pub name: &'static str,
     pub source: Option<FluidId>,
     pub sink: Option<FluidId>,
-    /// Input/output slots.
+    /// Input/output slots, four on square grids and six on hexagonal ones.
     ///
     /// Side indices:
     /// ```text
-    ///    0
-    /// 3 |P| 1
-    ///    2
+    ///    0            5 / \ 0
+    /// 3 |P| 1       4 | P | 1
+    ///    2            3 \ / 2
     /// ```
-    pub slots: [Slot; 4],
-    /// Progress as a float from 0 to 1
-    pub progress: f32,
-    /// How fast the progress fills, 1/s
+    pub slots: Vec<Slot>,
+    /// Clockwise turns of one side, rotating the slots with the model
+    pub rotation: u8,
+    /// Fluid running through the pipe, one entry per independent channel that has any
+    pub channels: Vec<Channel>,
+    /// How fast the progress of each channel fills, 1/s, before the speed of the fluid
     pub progress_rate: f32,
+    /// Multiplier on the speed of the fluid passing through, which it keeps downstream
+    pub pump: f32,
     pub internal_routing: Vec<InternalRouting>,
+    /// Containers at the internal slots after the sides, up to 99; slots without one hold no fluid
+    pub containers: Vec<Container>,
+    /// Model of the pipe, the default handle for pipes built from meshes, like hex pipes
     pub model: Handle<Scene>,
     pub locked: bool,
+    /// Valve the player can operate, also while the fluid is flowing
+    pub valve: Option<Valve>,
+    /// Whether the channel between sides 1 and 3 runs over the rest of the pipe
+    pub bridge: bool,
+    /// Fluid welling up at [`INTERNAL_SOURCE`]
+    pub spring: Option<Spring>,
+    /// Fluid draining away at [`INTERNAL_SINK`]
+    pub drain: Option<Drain>,
+    /// Link to another portal through [`PORTAL`]
+    pub portal: Option<Portal>,
+    /// Degrees added to the fluid passing through, negative for coolers
+    pub heating: f32,
+    /// Temperatures a sink accepts its fluid at, any if `None`
+    pub sink_temperature: Option<TemperatureRange>,
+}
+
+/// Pipe spanning several cells, made of one [`Pipe`] part per cell.
+///
+/// Parts connect to each other through the sides they share, like neighbouring pipes, and have
+/// slots of their own on the perimeter of the piece. The whole piece turns as one, see
+/// [`turn_footprint`].
+#[derive(Debug, Clone)]
+pub struct LargePipe {
+    /// Parts with their cell relative to the top-left cell of the piece, unturned
+    pub parts: Vec<(IVec2, Pipe)>,
+    /// Model of the whole piece, centered on it
+    pub model: Handle<Scene>,
+    /// Scale of the model, stretching it over the cells of the piece
+    pub model_scale: Vec3,
+    /// Whether fluid running into a part evens out its temperature with the fluid in the others
+    pub exchanges_heat: bool,
+}
+
+/// Spawned [`LargePipe`], holding its model. The parts are tiles of their own, with a
+/// [`PipePart`] pointing back here.
+#[derive(Component, Debug)]
+pub struct LargePipeRoot {
+    /// Top-left cell of the piece
+    pub origin: IVec2,
+    /// Clockwise quarter turns of the whole piece
+    pub rotation: u8,
+    /// Part tiles with their unturned offsets, as in [`LargePipe::parts`]
+    pub parts: Vec<(IVec2, Entity)>,
+    pub locked: bool,
+    pub exchanges_heat: bool,
+}
+
+/// Tile that is a part of the large pipe with the given root.
+#[derive(Component, Debug)]
+pub struct PipePart(pub Entity);
+
+/// Offsets of the parts of a large pipe turned `rotation` quarter turns clockwise, relative to the
+/// top-left cell of the turned piece.
+pub fn turn_footprint(offsets: &[IVec2], rotation: u8) -> Vec<IVec2> {
+    let turned: Vec<_> = offsets
+        .iter()
+        .map(|offset| {
+            (0..rotation % SIDES).fold(*offset, |offset, _| IVec2::new(-offset.y, offset.x))
+        })
+        .collect();
+    let min = turned
+        .iter()
+        .copied()
+        .reduce(IVec2::min)
+        .unwrap_or_default();
+    turned.into_iter().map(|offset| offset - min).collect()
+}
+
+/// Fluid in one channel of a pipe.
+///
+/// Sides connected by the internal routing form a channel. Most pipes have a single channel,
+/// crossovers and bridges carry two separate streams. Fluid reaching a side whose channel already
+/// has fluid stays out, so separate channels can carry different fluids.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub fluid: FluidId,
+    /// Local side the fluid came in through, `None` for sources
+    pub entry: Option<SlotId>,
+    /// Progress as a float from 0 to 1
+    pub progress: f32,
+    /// Temperature of the fluid, °C
+    pub temperature: f32,
+    /// Multiplier on the fill rate, from the viscosity of the fluid and the pumps it went through
+    pub speed: f32,
+}
+
+/// Internal container, holding back the fluid running through it until it is full.
+#[derive(Debug, Clone)]
+pub struct Container {
+    /// Internal routing slot, after the sides and up to 99
+    pub slot: SlotId,
+    /// Volume held when full, in pipe fills
+    pub capacity: f32,
+    /// Volume held now
+    pub volume: f32,
+    /// Sides whose fluid has mixed into the container, see [`Pipe::mixing_channel`]
+    pub mixed: Vec<SlotId>,
+}
+
+impl Container {
+    pub fn is_full(&self) -> bool {
+        self.volume >= self.capacity
+    }
+}
+
+/// Fluid made inside a tile, that starts flowing with the sources.
+///
+/// Volumes are in pipe fills, so a spring with a capacity of 1 fills one pipe. Everything the
+/// spring feeds draws on its volume, until it runs dry.
+#[derive(Debug, Clone)]
+pub struct Spring {
+    pub fluid: FluidId,
+    /// Volume welling up per second, which is also how fast the spring's own channel fills
+    pub rate: f32,
+    /// Volume the spring gives before it runs dry
+    pub capacity: f32,
+    /// Volume given so far
+    pub produced: f32,
+}
+
+impl Spring {
+    /// Whether the spring gave all its volume; a dry spring pushes no more fluid on.
+    pub fn is_dry(&self) -> bool {
+        self.produced >= self.capacity
+    }
+
+    /// Volume the spring has left to give.
+    pub fn remaining(&self) -> f32 {
+        (self.capacity - self.produced).max(0.0)
+    }
+}
+
+/// Fluid taken away inside a tile, once the channel leading to it is full and for as long as it
+/// is fed.
+#[derive(Debug, Clone)]
+pub struct Drain {
+    /// Volume taken per second, in pipe fills
+    pub rate: f32,
+    /// Volume the drain takes before it is full and lets the fluid back up
+    pub capacity: f32,
+    /// Volume taken so far
+    pub drained: f32,
+}
+
+impl Drain {
+    pub fn is_full(&self) -> bool {
+        self.drained >= self.capacity
+    }
+}
+
+/// Range of temperatures, °C.
+#[derive(Debug, Clone, Copy)]
+pub struct TemperatureRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl TemperatureRange {
+    pub fn contains(&self, temperature: f32) -> bool {
+        (self.min..=self.max).contains(&temperature)
+    }
+}
+
+/// Player-operated part of a pipe.
+#[derive(Debug, Clone)]
+pub enum Valve {
+    /// Lets fluid in and through only while open
+    Gate { open: bool },
+    /// Routes fluid from the inlet to the selected one of two outlets
+    Diverter {
+        inlet: SlotId,
+        outlets: [SlotId; 2],
+        selected: usize,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -183,8 +1174,10 @@ pub enum Function {
 
 /// Pipe routing internal to tile.
 ///
-/// Slot IDs 0 through 3 correspond to I/O slots, 4-99 are internal containers
-/// used for internal functions like mixing. Slot 100 is internal source, and 101 is internal sink.
+/// Slot IDs 0 through 3 correspond to I/O slots (0 through 5 on hex pipes), the following up to 99
+/// are internal containers used for internal functions like mixing. Slot 100 is internal source,
+/// and 101 is internal sink.
+/// See [`Pipe::validate`] for which slots a tile may use.
 #[derive(Debug, Clone)]
 pub struct InternalRouting {
     to: SlotId,
@@ -192,6 +1185,400 @@ pub struct InternalRouting {
     function: Function,
 }
 
+/// Portal passing the fluid on to its partner elsewhere on the grid.
+///
+/// Pairs are linked in the map, see [`crate::level::LevelData::portals`]. The fluid comes out of
+/// the partner through the side routed to its [`PORTAL`] slot.
+#[derive(Debug, Clone, Default)]
+pub struct Portal {
+    /// Tile of the paired portal, `None` for a portal without a partner
+    pub partner: Option<Entity>,
+}
+
+/// Problem with the internal routing of a pipe.
+#[derive(Debug, Error)]
+pub enum RoutingError {
+    #[error("slot {0} is not defined")]
+    UndefinedSlot(SlotId),
+    #[error("side {0} has no slot")]
+    ClosedSide(SlotId),
+    #[error("route from the internal source, but the tile has no spring")]
+    MissingSpring,
+    #[error("route to the internal sink, but the tile has no drain")]
+    MissingDrain,
+    #[error("route to the portal slot, but the tile is no portal")]
+    MissingPortal,
+    #[error("container at slot {0}, which is not an internal container slot")]
+    MisplacedContainer(SlotId),
+}
+
+impl Default for Pipe {
+    /// A four-sided pipe without slots, routing or model, which the archetypes are built from.
+    fn default() -> Self {
+        Pipe {
+            name: "pipe",
+            source: None,
+            sink: None,
+            slots: vec![Slot::None; SIDES as usize],
+            rotation: 0,
+            channels: vec![],
+            progress_rate: 1.0,
+            pump: 1.0,
+            internal_routing: vec![],
+            containers: vec![],
+            model: Handle::default(),
+            locked: false,
+            valve: None,
+            bridge: false,
+            spring: None,
+            drain: None,
+            portal: None,
+            heating: 0.0,
+            sink_temperature: None,
+        }
+    }
+}
+
+impl Pipe {
+    /// Checks that every route connects slots the tile has.
+    ///
+    /// Routes may use sides with a slot, the internal containers up to 99, the internal source if
+    /// the tile has a spring, the internal sink if it has a drain and the portal slot if it is a
+    /// portal.
+    pub fn validate(&self) -> Result<(), RoutingError> {
+        for route in &self.internal_routing {
+            for slot in [route.from, route.to] {
+                match slot {
+                    side if side < self.sides() => {
+                        if matches!(self.slots[side as usize], Slot::None) {
+                            return Err(RoutingError::ClosedSide(side));
+                        }
+                    }
+                    slot if slot < INTERNAL_SOURCE => {}
+                    INTERNAL_SOURCE if self.spring.is_none() => {
+                        return Err(RoutingError::MissingSpring);
+                    }
+                    INTERNAL_SINK if self.drain.is_none() => {
+                        return Err(RoutingError::MissingDrain);
+                    }
+                    PORTAL if self.portal.is_none() => {
+                        return Err(RoutingError::MissingPortal);
+                    }
+                    INTERNAL_SOURCE | INTERNAL_SINK | PORTAL => {}
+                    _ => return Err(RoutingError::UndefinedSlot(slot)),
+                }
+            }
+        }
+        if let Some(container) = self
+            .containers
+            .iter()
+            .find(|container| !(self.sides()..INTERNAL_SOURCE).contains(&container.slot))
+        {
+            return Err(RoutingError::MisplacedContainer(container.slot));
+        }
+        Ok(())
+    }
+
+    /// Whether fluid is held back from entering and running on, by a closed gate valve.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.valve, Some(Valve::Gate { open: false }))
+    }
+
+    /// Opens or closes a gate valve, or switches a diverter to its other outlet.
+    ///
+    /// Valves also work with fluid in them. The fluid past the valve is cut off, so a closed gate
+    /// holds the fluid up to the valve, and a diverter refills the rest of its channel towards
+    /// the new outlet.
+    pub fn toggle_valve(&mut self) {
+        let shares: Vec<_> = self
+            .channels
+            .iter()
+            .map(|channel| self.inflow_share(channel))
+            .collect();
+        match &mut self.valve {
+            Some(Valve::Gate { open }) => {
+                *open = !*open;
+                if *open {
+                    return;
+                }
+            }
+            Some(Valve::Diverter {
+                inlet,
+                outlets,
+                selected,
+            }) => {
+                *selected = 1 - *selected;
+                self.internal_routing =
+                    vec![InternalRouting::passthrough(*inlet, outlets[*selected])];
+            }
+            None => return,
+        }
+        for (channel, share) in self.channels.iter_mut().zip(shares) {
+            channel.progress = channel.progress.min(share);
+        }
+    }
+
+    /// Temperature of fluid coming in at `temperature` once it has run through this pipe.
+    ///
+    /// The fluid cools towards the ambient temperature on the way, then heaters and coolers
+    /// change it.
+    pub fn passed_temperature(&self, temperature: f32) -> f32 {
+        AMBIENT_TEMPERATURE
+            + (temperature - AMBIENT_TEMPERATURE) * (1.0 - COOLING_PER_PIPE)
+            + self.heating
+    }
+
+    /// Number of sides of the tile.
+    pub fn sides(&self) -> SlotId {
+        self.slots.len() as SlotId
+    }
+
+    /// Local side a channel's fluid came in through, `None` for fluid from sources, springs and
+    /// portals.
+    pub fn inlet(&self, channel: &Channel) -> Option<SlotId> {
+        channel.entry.filter(|entry| *entry < self.sides())
+    }
+
+    /// Local side that currently faces the given world side.
+    pub fn local_side(&self, side: SlotId) -> SlotId {
+        let sides = self.sides();
+        (side % sides + sides - self.rotation % sides) % sides
+    }
+
+    /// World side that the given local side currently faces.
+    pub fn world_side(&self, local: SlotId) -> SlotId {
+        (local + self.rotation) % self.sides()
+    }
+
+    /// Slot facing the given world side.
+    pub fn slot(&self, side: SlotId) -> &Slot {
+        &self.slots[self.local_side(side) as usize]
+    }
+
+    /// Whether any channel has fluid in it.
+    pub fn has_fluid(&self) -> bool {
+        !self.channels.is_empty()
+    }
+
+    /// Whether the pipe has fluid and all of its filled channels are full.
+    pub fn is_full(&self) -> bool {
+        self.has_fluid()
+            && self
+                .channels
+                .iter()
+                .all(|channel| self.is_channel_full(channel))
+    }
+
+    /// Whether the fluid has run all through a channel, filling the containers on its way and
+    /// taking in the fluid of every other side that mixes into them.
+    pub fn is_channel_full(&self, channel: &Channel) -> bool {
+        channel.progress >= 1.0
+            && self.containers_on(channel.entry).all(|index| {
+                let container = &self.containers[index];
+                container.is_full()
+                    && self
+                        .mix_inlets(container.slot)
+                        .all(|side| channel.entry == Some(side) || container.mixed.contains(&side))
+            })
+    }
+
+    /// Sides routed into `slot` with [`Function::Mix`].
+    fn mix_inlets(&self, slot: SlotId) -> impl Iterator<Item = SlotId> + '_ {
+        self.internal_routing
+            .iter()
+            .filter(move |route| route.to == slot && matches!(route.function, Function::Mix))
+            .map(|route| route.from)
+    }
+
+    /// Channel that the fluid coming in through `side` mixes into, with the index of the
+    /// container it mixes in.
+    ///
+    /// The first fluid to run into a container that several sides mix into has the channel, the
+    /// fluid of the other sides joins it there.
+    pub fn mixing_channel(&self, side: SlotId) -> Option<(usize, usize)> {
+        self.containers
+            .iter()
+            .enumerate()
+            .filter(|(_, container)| self.mix_inlets(container.slot).any(|inlet| inlet == side))
+            .find_map(|(container_index, container)| {
+                let index = self.channels.iter().position(|channel| {
+                    channel.entry.is_some_and(|entry| {
+                        entry != side && self.reachable(entry).contains(&container.slot)
+                    })
+                })?;
+                Some((index, container_index))
+            })
+    }
+
+    /// Mixes fluid coming in through `side` into its mixing channel, see
+    /// [`Pipe::mixing_channel`], evening out their temperatures.
+    ///
+    /// Every side mixes in once. Returns whether the fluid mixed in; fluids that don't mix, see
+    /// [`mixture`], stay out.
+    pub fn mix_in(&mut self, side: SlotId, fluid: &FluidId, temperature: f32) -> bool {
+        let Some((index, container)) = self.mixing_channel(side) else {
+            return false;
+        };
+        if self.containers[container].mixed.contains(&side) {
+            return false;
+        }
+        let temperature = self.passed_temperature(temperature);
+        let channel = &mut self.channels[index];
+        let Some(mixed) = mixture(&channel.fluid, fluid) else {
+            return false;
+        };
+        channel.fluid = mixed;
+        channel.temperature = (channel.temperature + temperature) / 2.0;
+        self.containers[container].mixed.push(side);
+        true
+    }
+
+    /// Share of the channel progress taken by the fluid running in from the entry to the center.
+    pub fn inflow_share(&self, channel: &Channel) -> f32 {
+        match (
+            self.inlet(channel).is_some(),
+            !self.exits(channel.entry).is_empty(),
+        ) {
+            (true, true) => 0.5,
+            (true, false) => 1.0,
+            (false, _) => 0.0,
+        }
+    }
+
+    /// Indices of the containers that the fluid coming in through `entry` runs through.
+    pub fn containers_on(&self, entry: Option<SlotId>) -> impl Iterator<Item = usize> + '_ {
+        let path = entry.map_or_else(Vec::new, |entry| self.reachable(entry));
+        self.containers
+            .iter()
+            .enumerate()
+            .filter(move |(_, container)| path.contains(&container.slot))
+            .map(|(index, _)| index)
+    }
+
+    /// Volume per second flowing into a channel, in pipe fills.
+    ///
+    /// The fluid keeps the speed it got from pumps in every channel it fills further on.
+    pub fn fill_rate(&self, channel: &Channel) -> f32 {
+        let rate = match &self.spring {
+            Some(spring) if channel.entry == Some(INTERNAL_SOURCE) => spring.rate,
+            _ => self.progress_rate,
+        };
+        rate * channel.speed
+    }
+
+    /// Volume still missing from a channel coming in through `entry` with the given progress,
+    /// counting the containers on its way.
+    pub fn volume_to_fill(&self, entry: Option<SlotId>, progress: f32) -> f32 {
+        (1.0 - progress).max(0.0)
+            + self
+                .containers_on(entry)
+                .map(|index| {
+                    let container = &self.containers[index];
+                    (container.capacity - container.volume).max(0.0)
+                })
+                .sum::<f32>()
+    }
+
+    /// Pours fluid into a channel.
+    ///
+    /// The fluid first runs in from the entry to the center, then fills the containers on its
+    /// way, and only then runs out towards the exits. Volumes are in pipe fills, the same unit as
+    /// the channel progress.
+    pub fn pour(&mut self, index: usize, mut volume: f32) {
+        let Some(channel) = self.channels.get(index) else {
+            return;
+        };
+        let share = self.inflow_share(channel);
+        let containers: Vec<_> = self.containers_on(channel.entry).collect();
+
+        let channel = &mut self.channels[index];
+        let inflow = (share - channel.progress).clamp(0.0, volume);
+        channel.progress += inflow;
+        volume -= inflow;
+        for container in containers {
+            let container = &mut self.containers[container];
+            let poured = (container.capacity - container.volume).clamp(0.0, volume);
+            container.volume += poured;
+            volume -= poured;
+        }
+        let channel = &mut self.channels[index];
+        channel.progress = (channel.progress + volume).min(1.0);
+    }
+
+    /// Channel with fluid that runs through the given local side.
+    pub fn channel_at(&self, side: SlotId) -> Option<&Channel> {
+        self.channels.iter().find(|channel| {
+            channel.entry == Some(side) || self.exits(channel.entry).contains(&side)
+        })
+    }
+
+    /// Local sides the fluid coming in through `entry` leaves through once its channel is full.
+    ///
+    /// Sources emit through their output slots. Otherwise the fluid follows the internal routing
+    /// from the entry side, in either direction, to every other side that can emit.
+    pub fn exits(&self, entry: Option<SlotId>) -> Vec<SlotId> {
+        let Some(entry) = entry else {
+            return (0..self.sides())
+                .filter(|side| matches!(self.slots[*side as usize], Slot::Output))
+                .collect();
+        };
+
+        self.reachable(entry)
+            .into_iter()
+            .filter(|slot| {
+                *slot != entry && *slot < self.sides() && self.slots[*slot as usize].emits_output()
+            })
+            .collect()
+    }
+
+    /// Whether the fluid coming in through `entry` runs into the drain.
+    pub fn drains(&self, entry: Option<SlotId>) -> bool {
+        self.drain.is_some()
+            && entry.is_some_and(|entry| self.reachable(entry).contains(&INTERNAL_SINK))
+    }
+
+    /// Paired portal that the fluid coming in through `entry` passes over to.
+    ///
+    /// Fluid that came out of the portal doesn't go back in, and fluid only comes out of the
+    /// partner while no other fluid runs through it, see [`Pipe::is_portal_in_use`].
+    pub fn teleports(&self, entry: Option<SlotId>) -> Option<Entity> {
+        let partner = self.portal.as_ref()?.partner?;
+        let entry = entry.filter(|entry| *entry != PORTAL)?;
+        self.reachable(entry).contains(&PORTAL).then_some(partner)
+    }
+
+    /// Whether fluid runs through the portal slot, going in or coming out.
+    pub fn is_portal_in_use(&self) -> bool {
+        self.channels.iter().any(|channel| {
+            channel
+                .entry
+                .is_some_and(|entry| self.reachable(entry).contains(&PORTAL))
+        })
+    }
+
+    /// Slots connected to `slot` by the internal routing, in either direction, including itself.
+    fn reachable(&self, slot: SlotId) -> Vec<SlotId> {
+        let mut visited = vec![slot];
+        let mut open = vec![slot];
+        while let Some(slot) = open.pop() {
+            for route in &self.internal_routing {
+                let next = if route.from == slot {
+                    route.to
+                } else if route.to == slot {
+                    route.from
+                } else {
+                    continue;
+                };
+                if !visited.contains(&next) {
+                    visited.push(next);
+                    open.push(next);
+                }
+            }
+        }
+        visited
+    }
+}
+
 impl InternalRouting {
     pub fn passthrough(from: SlotId, to: SlotId) -> Self {
         InternalRouting {
@@ -209,3 +1596,52 @@ impl InternalRouting {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_footprint_of_a_row() {
+        let offsets = [IVec2::new(0, 0), IVec2::new(1, 0)];
+        assert_eq!(turn_footprint(&offsets, 0), offsets);
+        assert_eq!(
+            turn_footprint(&offsets, 1),
+            [IVec2::new(0, 0), IVec2::new(0, 1)]
+        );
+        assert_eq!(
+            turn_footprint(&offsets, 2),
+            [IVec2::new(1, 0), IVec2::new(0, 0)]
+        );
+        assert_eq!(turn_footprint(&offsets, 4), offsets);
+    }
+
+    #[test]
+    fn turn_footprint_of_a_square() {
+        let offsets = [
+            IVec2::new(0, 0),
+            IVec2::new(1, 0),
+            IVec2::new(0, 1),
+            IVec2::new(1, 1),
+        ];
+        // A quarter turn clockwise moves the top-left part to the top right
+        assert_eq!(
+            turn_footprint(&offsets, 1),
+            [
+                IVec2::new(1, 0),
+                IVec2::new(1, 1),
+                IVec2::new(0, 0),
+                IVec2::new(0, 1),
+            ]
+        );
+        assert_eq!(
+            turn_footprint(&offsets, 3),
+            [
+                IVec2::new(0, 1),
+                IVec2::new(0, 0),
+                IVec2::new(1, 1),
+                IVec2::new(1, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Placement mode, where the player builds the network from a queue of pipes
+
+use crate::AppState;
+use crate::audio::{PlaySfx, Sfx};
+use crate::game::{self, GameEntity, Moves, PipeGameState};
+use crate::level::{CurrentLevel, GameMode, GridCell, Level, LevelGrid, TILE_SIZE};
+use crate::pipes::{self, Pipe, PipeArchetypes, Slot, SlotId};
+use crate::swap::Dragged;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+pub struct PlacementPlugin;
+
+impl Plugin for PlacementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::InGame), setup_placement)
+            .add_systems(OnExit(AppState::InGame), cleanup);
+    }
+}
+
+/// Number of upcoming pieces shown to the player.
+const QUEUE_LENGTH: usize = 5;
+
+/// A pipe waiting in the queue: archetype id and clockwise turns, by one side each.
+#[derive(Debug, Clone, Copy)]
+pub struct Piece {
+    pub archetype: u32,
+    pub rotation: u8,
+    /// Sides of the pipe, for the angle of a turn
+    pub sides: SlotId,
+}
+
+impl Piece {
+    /// Short description for the queue preview, naming the pipe after its archetype.
+    pub fn label(&self, archetypes: &PipeArchetypes) -> String {
+        let name = archetypes
+            .get(&self.archetype)
+            .map_or("pipe", |pipe| pipe.name);
+        format!("{name} {}°", self.rotation as u32 * 360 / self.sides as u32)
+    }
+}
+
+/// Upcoming pieces in placement mode, next piece first.
+#[derive(Resource, Debug)]
+pub struct PieceQueue {
+    pub pieces: VecDeque<Piece>,
+    /// Number of placed pipes that were replaced, each costing points
+    pub replacements: u32,
+    /// Archetypes the queue draws from
+    pool: Vec<u32>,
+    /// Sides of the grid cells, which the random rotations turn through
+    sides: SlotId,
+    rng: fastrand::Rng,
+}
+
+impl PieceQueue {
+    fn new(archetypes: &PipeArchetypes, sides: SlotId) -> Self {
+        // Only pieces that fit the grid and can carry fluid through, so no sources, sinks or corks
+        let mut pool: Vec<u32> = archetypes
+            .iter()
+            .filter(|(_, pipe)| {
+                pipe.sides() == sides
+                    && pipe.source.is_none()
+                    && pipe.sink.is_none()
+                    && pipe
+                        .slots
+                        .iter()
+                        .filter(|slot| !matches!(slot, Slot::None))
+                        .count()
+                        >= 2
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        pool.sort_unstable();
+
+        let mut queue = PieceQueue {
+            pieces: VecDeque::with_capacity(QUEUE_LENGTH),
+            replacements: 0,
+            pool,
+            sides,
+            rng: fastrand::Rng::new(),
+        };
+        for _ in 0..QUEUE_LENGTH {
+            queue.push_random();
+        }
+        queue
+    }
+
+    fn push_random(&mut self) {
+        if let Some(archetype) = self.rng.choice(self.pool.iter().copied()) {
+            let rotation = self.rng.u8(..self.sides);
+            self.pieces.push_back(Piece {
+                archetype,
+                rotation,
+                sides: self.sides,
+            });
+        }
+    }
+
+    /// Takes the next piece and draws a new one to the back of the queue.
+    fn pop(&mut self) -> Option<Piece> {
+        let piece = self.pieces.pop_front()?;
+        self.push_random();
+        Some(piece)
+    }
+}
+
+/// Marker for the clickable floor of a grid cell in placement mode.
+#[derive(Component, Debug)]
+struct CellFloor;
+
+pub fn setup_placement(
+    mut commands: Commands,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+    archetypes: Res<PipeArchetypes>,
+    grid: Res<LevelGrid>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Some(level) = levels.get(&current_level.0) else {
+        return;
+    };
+    if level.mode != GameMode::Placement {
+        return;
+    }
+
+    info!("Setting up placement mode");
+    commands.insert_resource(PieceQueue::new(&archetypes, grid.shape.sides()));
+
+    let mesh = meshes.add(
+        Plane3d::default()
+            .mesh()
+            .size(TILE_SIZE * 0.9, TILE_SIZE * 0.9),
+    );
+    let material = materials.add(StandardMaterial {
+        base_color: Color::srgba(1.0, 1.0, 1.0, 0.15),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    for cell in grid.empty_cells() {
+        let center = level.data.cell_center(cell);
+        commands
+            .spawn((
+                Mesh3d(mesh.clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_xyz(center.x, 0., center.y),
+                GridCell(cell),
+                CellFloor,
+                GameEntity,
+            ))
+            .observe(place_piece);
+    }
+}
+
+fn cleanup(mut commands: Commands) {
+    commands.remove_resource::<PieceQueue>();
+}
+
+/// Places the next queued piece on a clicked empty cell, or over a placed pipe.
+///
+/// Replacing a pipe throws it away and counts against the score. Pipes with fluid in them and
+/// the fixed tiles of the level can't be replaced.
+fn place_piece(
+    trigger: Trigger<Pointer<Click>>,
+    mut commands: Commands,
+    cells: Query<&GridCell>,
+    pipes: Query<&Pipe, Without<Dragged>>,
+    mut grid: ResMut<LevelGrid>,
+    queue: Option<ResMut<PieceQueue>>,
+    archetypes: Res<PipeArchetypes>,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    mut moves: ResMut<Moves>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    if !game::can_interact(game_state.as_deref()) {
+        return;
+    }
+    let (Some(mut queue), Some(level)) = (queue, levels.get(&current_level.0)) else {
+        return;
+    };
+    let Ok(&GridCell(cell)) = cells.get(trigger.target()) else {
+        return;
+    };
+
+    let replaced = grid.get(cell);
+    if let Some(old) = replaced {
+        match pipes.get(old) {
+            Ok(pipe) if !pipe.locked && !pipe.has_fluid() => {}
+            _ => return,
+        }
+    }
+    // Only take the piece once it is sure to be placed
+    let Some(archetype) = queue
+        .pieces
+        .front()
+        .and_then(|piece| archetypes.get(&piece.archetype))
+    else {
+        return;
+    };
+    let Some(piece) = queue.pop() else {
+        return;
+    };
+    if let Some(old) = replaced {
+        commands.entity(old).despawn();
+        queue.replacements += 1;
+    }
+
+    let mut pipe = archetype.clone();
+    pipe.rotation = piece.rotation;
+    let center = level.data.cell_center(cell);
+    let mut entity = commands.spawn((
+        Transform::from_xyz(center.x, 0., center.y)
+            .with_rotation(pipes::rotation_quat(pipe.rotation, pipe.sides())),
+        GridCell(cell),
+        GameEntity,
+    ));
+    entity.observe(place_piece);
+    pipes::spawn_pipe_visual(&mut entity, &pipe);
+    grid.set(cell, entity.insert(pipe).id());
+    moves.0 += 1;
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
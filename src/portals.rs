@@ -0,0 +1,76 @@
+//! Rings marking which portals are paired
+
+use crate::pipes::Pipe;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct PortalsPlugin;
+
+impl Plugin for PortalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_portal_assets)
+            .add_systems(Update, (spawn_portal_rings, spin_portal_rings));
+    }
+}
+
+/// Ring turns per second.
+const RING_SPEED: f32 = 0.5;
+
+/// Hue step between the colours of successive pairs, in degrees.
+const PAIR_HUE_STEP: f32 = 137.5;
+
+#[derive(Resource, Debug)]
+struct PortalAssets {
+    ring: Handle<Mesh>,
+}
+
+/// Glowing ring over a portal, in the colour of its pair.
+#[derive(Component, Debug)]
+struct PortalRing;
+
+fn initialize_portal_assets(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(PortalAssets {
+        ring: meshes.add(Torus::new(0.35, 0.45)),
+    });
+}
+
+/// Adds a ring over each newly spawned portal, both portals of a pair getting the same colour.
+fn spawn_portal_rings(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    assets: Res<PortalAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, pipe) in &pipes {
+        let Some(portal) = &pipe.portal else {
+            continue;
+        };
+        let color = match portal.partner {
+            // Derive the hue from the pair, not from which end of it this is
+            Some(partner) => {
+                let pair = entity.index().min(partner.index());
+                Color::hsl((pair as f32 * PAIR_HUE_STEP) % 360., 0.9, 0.6)
+            }
+            None => Color::srgb(0.4, 0.4, 0.4),
+        };
+        let material = materials.add(StandardMaterial {
+            base_color: color,
+            emissive: LinearRgba::from(color) * 2.,
+            unlit: true,
+            ..default()
+        });
+        // The torus lies flat, stand it up facing the open side of the portal
+        commands.entity(entity).with_child((
+            PortalRing,
+            Mesh3d(assets.ring.clone()),
+            MeshMaterial3d(material),
+            Transform::from_xyz(0., 0.6, 0.).with_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+        ));
+    }
+}
+
+fn spin_portal_rings(mut rings: Query<&mut Transform, With<PortalRing>>, time: Res<Time>) {
+    for mut transform in &mut rings {
+        transform.rotate_local_y(RING_SPEED * std::f32::consts::TAU * time.delta_secs());
+    }
+}
@@ -0,0 +1,100 @@
+//! Pump rotors and narrow pipe collars
+
+use crate::pipes::Pipe;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct PumpsPlugin;
+
+impl Plugin for PumpsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_pump_assets)
+            .add_systems(Update, (spawn_pump_parts, spin_rotors));
+    }
+}
+
+/// Rotor turns per second for each unit of pump boost.
+const ROTOR_SPEED: f32 = 1.5;
+
+#[derive(Resource, Debug)]
+struct PumpAssets {
+    housing: Handle<Mesh>,
+    blade: Handle<Mesh>,
+    collar: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// Rotor on top of a pump, spinning while fluid goes through it.
+#[derive(Component, Debug)]
+struct Rotor;
+
+fn initialize_pump_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(PumpAssets {
+        housing: meshes.add(Cylinder::new(0.3, 0.2)),
+        blade: meshes.add(Cuboid::new(0.7, 0.05, 0.12)),
+        collar: meshes.add(Torus::new(0.4, 0.5)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.9, 0.75, 0.2),
+            metallic: 0.6,
+            perceptual_roughness: 0.4,
+            ..default()
+        }),
+    });
+}
+
+/// Adds a rotor on each newly spawned pump, and collars pinching each narrow pipe.
+fn spawn_pump_parts(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    assets: Res<PumpAssets>,
+) {
+    for (entity, pipe) in &pipes {
+        if pipe.pump > 1.0 {
+            commands.entity(entity).with_children(|parent| {
+                parent.spawn((
+                    Mesh3d(assets.housing.clone()),
+                    MeshMaterial3d(assets.material.clone()),
+                    Transform::from_xyz(0.0, 0.5, 0.0),
+                ));
+                parent.spawn((
+                    Rotor,
+                    Mesh3d(assets.blade.clone()),
+                    MeshMaterial3d(assets.material.clone()),
+                    Transform::from_xyz(0.0, 0.65, 0.0),
+                ));
+            });
+        }
+        if pipe.progress_rate < 1.0 {
+            // The torus lies flat, stand it up around the model axis running from side 0 to side 2
+            commands.entity(entity).with_children(|parent| {
+                for x in [-0.3, 0.3] {
+                    parent.spawn((
+                        Mesh3d(assets.collar.clone()),
+                        MeshMaterial3d(assets.material.clone()),
+                        Transform::from_xyz(x, 0.0, 0.0)
+                            .with_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+                    ));
+                }
+            });
+        }
+    }
+}
+
+fn spin_rotors(
+    mut rotors: Query<(&ChildOf, &mut Transform), With<Rotor>>,
+    pipes: Query<&Pipe>,
+    time: Res<Time>,
+) {
+    for (child_of, mut transform) in &mut rotors {
+        let Ok(pipe) = pipes.get(child_of.parent()) else {
+            continue;
+        };
+        if pipe.has_fluid() {
+            transform.rotate_y(ROTOR_SPEED * pipe.pump * std::f32::consts::TAU * time.delta_secs());
+        }
+    }
+}
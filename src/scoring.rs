@@ -0,0 +1,153 @@
+//! Level scores, star ratings and player progress
+
+use crate::game::{FlowTime, Moves, PipeGameState, PrepareTimer};
+use crate::level::{CurrentLevel, Level};
+use crate::pipes::Pipe;
+use crate::placement::PieceQueue;
+use crate::storage;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub struct ScoringPlugin;
+
+impl Plugin for ScoringPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(storage::load::<Progress>(PROGRESS_PATH))
+            .add_systems(OnEnter(PipeGameState::LevelWon), score_level);
+    }
+}
+
+/// Points per second of prepare time left when the flow started, or of flow time under par.
+const POINTS_PER_SECOND: f32 = 10.0;
+
+/// Points per pipe filled with fluid, not counting sources and sinks.
+const POINTS_PER_PIPE: u32 = 50;
+
+/// Points lost per pipe rotation.
+const POINTS_PER_MOVE: u32 = 5;
+
+/// Points lost per placed pipe replaced in placement mode.
+const POINTS_PER_REPLACEMENT: u32 = 25;
+
+/// Points per completed bonus objective.
+const POINTS_PER_BONUS: u32 = 250;
+
+/// File the progress is kept in, see [`storage`].
+const PROGRESS_PATH: &str = "progress.ron";
+
+/// Result of the level that was just won.
+#[derive(Resource, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LevelScore {
+    pub score: u32,
+    pub stars: u8,
+    /// Number of bonus objectives completed
+    pub bonuses: u32,
+}
+
+/// Best result per level, keyed by level id.
+///
+/// Saved after every won level, and read back on startup.
+#[derive(Resource, Debug, Default, Serialize, Deserialize)]
+pub struct Progress(HashMap<String, LevelScore>);
+
+impl Progress {
+    pub fn get(&self, level_id: &str) -> Option<&LevelScore> {
+        self.0.get(level_id)
+    }
+
+    /// Records a result, keeping the best score and star rating seen so far.
+    fn record(&mut self, level_id: &str, result: LevelScore) {
+        let best = self.0.entry(level_id.to_string()).or_insert(result);
+        best.score = best.score.max(result.score);
+        best.stars = best.stars.max(result.stars);
+        best.bonuses = best.bonuses.max(result.bonuses);
+    }
+}
+
+/// Scores the won level from the time saved before and during the flow, moves made, pipes filled
+/// and bonus objectives.
+pub fn score_level(
+    mut commands: Commands,
+    levels: Res<Assets<Level>>,
+    current_level: Res<CurrentLevel>,
+    prepare_timer: Option<Res<PrepareTimer>>,
+    flow_time: Option<Res<FlowTime>>,
+    moves: Res<Moves>,
+    queue: Option<Res<PieceQueue>>,
+    pipes: Query<&Pipe>,
+    mut progress: ResMut<Progress>,
+) {
+    let Some(level) = levels.get(&current_level.0) else {
+        return;
+    };
+    let rules = &level.scoring;
+
+    let flow_secs = flow_time.map_or(0.0, |time| time.0.elapsed_secs());
+    let remaining = prepare_timer.map_or(0.0, |timer| timer.0.remaining_secs())
+        + rules.par_time.map_or(0.0, |par| (par - flow_secs).max(0.0));
+    let length = pipes
+        .iter()
+        .filter(|pipe| pipe.source.is_none() && pipe.sink.is_none() && pipe.is_full())
+        .count() as u32;
+
+    let bonuses = [
+        rules.par_moves.map(|par| moves.0 <= par),
+        rules.bonus_length.map(|bonus| length >= bonus),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|done| *done)
+    .count() as u32;
+
+    let replacements = queue.map_or(0, |queue| queue.replacements);
+    let score = ((remaining * POINTS_PER_SECOND) as u32
+        + length * POINTS_PER_PIPE
+        + bonuses * POINTS_PER_BONUS)
+        .saturating_sub(moves.0 * POINTS_PER_MOVE + replacements * POINTS_PER_REPLACEMENT);
+    let stars = 1 + rules
+        .star_thresholds
+        .iter()
+        .filter(|threshold| score >= **threshold)
+        .count() as u8;
+
+    let result = LevelScore {
+        score,
+        stars,
+        bonuses,
+    };
+    info!("Level {} scored {:?}", level.id, result);
+    progress.record(&level.id, result);
+    storage::save(PROGRESS_PATH, &*progress);
+    commands.insert_resource(result);
+}
+
+/// Row of star markers, with the first `stars` lit.
+///
+/// The UI fonts have no star glyph, so stars are drawn as round nodes.
+pub fn star_row(stars: u8, size: f32) -> impl Bundle {
+    let star = move |index: u8| {
+        let color = if index < stars {
+            Color::srgb(1.0, 0.8, 0.2)
+        } else {
+            Color::srgba(1.0, 1.0, 1.0, 0.2)
+        };
+        (
+            Node {
+                width: Val::Px(size),
+                height: Val::Px(size),
+                ..default()
+            },
+            BorderRadius::MAX,
+            BackgroundColor(color),
+        )
+    };
+    (
+        Node {
+            column_gap: Val::Px(size / 3.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        children![star(0), star(1), star(2)],
+    )
+}
@@ -0,0 +1,158 @@
+//! Progress bars over sinks with delivery requirements
+
+use crate::flow::SinkGoal;
+use crate::game::FlowTime;
+use crate::level::SinkRequirements;
+use crate::pipes::Pipe;
+use bevy::prelude::*;
+
+pub struct SinksPlugin;
+
+impl Plugin for SinksPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_bar_assets)
+            .add_systems(Update, (spawn_goal_bars, update_goal_bars).chain());
+    }
+}
+
+/// Length of a full progress bar.
+const BAR_LENGTH: f32 = 1.6;
+
+/// Space between the bars of a sink, across the bars.
+const BAR_SPACING: f32 = 0.3;
+
+/// Height of the bars above the sink.
+const BAR_HEIGHT: f32 = 1.4;
+
+#[derive(Resource, Debug)]
+struct BarAssets {
+    bar: Handle<Mesh>,
+    background: Handle<StandardMaterial>,
+    volume: Handle<StandardMaterial>,
+    hold: Handle<StandardMaterial>,
+    deadline: Handle<StandardMaterial>,
+}
+
+/// Holder of the bars over a sink, cancelling out the rotation of the sink so they stay level.
+#[derive(Component, Debug)]
+struct GoalBars;
+
+/// Filled part of a progress bar.
+#[derive(Component, Debug, Clone, Copy)]
+enum GoalBar {
+    /// Share of the required volume taken in
+    Volume,
+    /// Share of the hold time the sink has stayed full
+    Hold,
+    /// Time left until the deadline
+    Deadline,
+}
+
+fn initialize_bar_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        })
+    };
+    commands.insert_resource(BarAssets {
+        bar: meshes.add(Cuboid::new(1.0, 0.05, 0.2)),
+        background: material(Color::srgb(0.1, 0.1, 0.1)),
+        volume: material(Color::srgb(0.2, 0.6, 1.0)),
+        hold: material(Color::srgb(1.0, 0.7, 0.1)),
+        deadline: material(Color::srgb(0.9, 0.2, 0.2)),
+    });
+}
+
+/// Adds progress bars over each newly spawned sink that needs more than being filled once.
+fn spawn_goal_bars(
+    mut commands: Commands,
+    sinks: Query<(Entity, &SinkGoal), Added<SinkGoal>>,
+    assets: Res<BarAssets>,
+) {
+    for (entity, goal) in &sinks {
+        let requirements = &goal.requirements;
+        if *requirements == SinkRequirements::default() {
+            continue;
+        }
+        let bars = [
+            Some((GoalBar::Volume, &assets.volume)),
+            requirements.hold.map(|_| (GoalBar::Hold, &assets.hold)),
+            requirements
+                .deadline
+                .map(|_| (GoalBar::Deadline, &assets.deadline)),
+        ];
+        commands.entity(entity).with_children(|parent| {
+            parent
+                .spawn((GoalBars, Transform::default(), Visibility::default()))
+                .with_children(|parent| {
+                    for (row, (bar, material)) in bars.into_iter().flatten().enumerate() {
+                        let z = row as f32 * BAR_SPACING;
+                        parent.spawn((
+                            Mesh3d(assets.bar.clone()),
+                            MeshMaterial3d(assets.background.clone()),
+                            Transform::from_xyz(0., BAR_HEIGHT, z)
+                                .with_scale(Vec3::new(BAR_LENGTH, 1.0, 1.0)),
+                        ));
+                        // Slightly above the background, so it doesn't flicker through
+                        parent.spawn((
+                            bar,
+                            Mesh3d(assets.bar.clone()),
+                            MeshMaterial3d(material.clone()),
+                            Transform::from_xyz(0., BAR_HEIGHT + 0.01, z)
+                                .with_scale(Vec3::new(0.0, 1.0, 1.0)),
+                        ));
+                    }
+                });
+        });
+    }
+}
+
+/// Keeps the bars level over their sink and fills them to match the progress of the sink.
+fn update_goal_bars(
+    mut holders: Query<(&ChildOf, &mut Transform), With<GoalBars>>,
+    mut bars: Query<(&GoalBar, &ChildOf, &mut Transform), Without<GoalBars>>,
+    sinks: Query<(&Pipe, &SinkGoal, &Transform), (Without<GoalBars>, Without<GoalBar>)>,
+    flow_time: Option<Res<FlowTime>>,
+) {
+    for (child_of, mut transform) in &mut holders {
+        if let Ok((_, _, sink_transform)) = sinks.get(child_of.parent()) {
+            let level = sink_transform.rotation.inverse();
+            if transform.rotation != level {
+                transform.rotation = level;
+            }
+        }
+    }
+
+    let elapsed = flow_time.map_or(0.0, |flow_time| flow_time.0.elapsed_secs());
+    for (bar, child_of, mut transform) in &mut bars {
+        let Ok((holder, _)) = holders.get(child_of.parent()) else {
+            continue;
+        };
+        let Ok((pipe, goal, _)) = sinks.get(holder.parent()) else {
+            continue;
+        };
+        let progress = match bar {
+            GoalBar::Volume => goal.volume_progress(pipe),
+            GoalBar::Hold => goal.hold_progress(),
+            // Stops running down once the sink is done
+            GoalBar::Deadline if goal.met => continue,
+            GoalBar::Deadline => goal
+                .requirements
+                .deadline
+                .map_or(0.0, |deadline| (1.0 - elapsed / deadline).clamp(0.0, 1.0)),
+        };
+        let length = progress * BAR_LENGTH;
+        // Grows from the left end of the background
+        let x = (length - BAR_LENGTH) / 2.0;
+        if transform.scale.x != length || transform.translation.x != x {
+            transform.scale.x = length;
+            transform.translation.x = x;
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Sliding puzzle mode, where pipes move into neighbouring empty cells
+
+use crate::audio::{PlaySfx, Sfx};
+use crate::game::{self, Moves, PipeGameState};
+use crate::level::{CurrentLevel, GameMode, GridCell, Level, LevelGrid};
+use crate::pipes::Pipe;
+use bevy::prelude::*;
+
+pub struct SlidingPlugin;
+
+impl Plugin for SlidingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(slide_pipe)
+            .add_systems(Update, animate_slides);
+    }
+}
+
+/// Seconds a pipe takes to slide into the next cell.
+const SLIDE_TIME: f32 = 0.15;
+
+/// Pipe moving between two cells.
+#[derive(Component, Debug)]
+struct Slide {
+    from: Vec3,
+    to: Vec3,
+    timer: Timer,
+}
+
+/// Slides a clicked pipe into the empty cell next to it, picking the one on the side that was
+/// clicked when there are several.
+///
+/// The grid is updated right away, so the flow sees the pipe in its new cell while it is still
+/// moving there. Pipes with fluid in them, locked pipes and pipes with no empty neighbour stay put.
+fn slide_pipe(
+    trigger: Trigger<Pointer<Click>>,
+    mut commands: Commands,
+    mut pipes: Query<(&Pipe, &mut GridCell, &Transform), Without<Slide>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    grid: Option<ResMut<LevelGrid>>,
+    moves: Option<ResMut<Moves>>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    if !game::can_interact(game_state.as_deref()) {
+        return;
+    }
+    let Some(level) = current_level.and_then(|current_level| levels.get(&current_level.0)) else {
+        return;
+    };
+    if level.mode != GameMode::Sliding {
+        return;
+    }
+    let (Some(mut grid), Some(mut moves)) = (grid, moves) else {
+        return;
+    };
+    let Ok((pipe, mut cell, transform)) = pipes.get_mut(trigger.target()) else {
+        return;
+    };
+    if pipe.locked || pipe.has_fluid() {
+        return;
+    }
+
+    let center = level.data.cell_center(cell.0);
+    let clicked = trigger
+        .event()
+        .hit
+        .position
+        .map_or(Vec2::ZERO, |position| position.xz() - center);
+    let Some(target) = (0..grid.shape.sides())
+        .map(|side| grid.neighbour(cell.0, side))
+        .filter(|neighbour| grid.contains(*neighbour) && grid.get(*neighbour).is_none())
+        .max_by(|a, b| {
+            let towards = |neighbour: &IVec2| {
+                (level.data.cell_center(*neighbour) - center)
+                    .normalize()
+                    .dot(clicked)
+            };
+            towards(a).total_cmp(&towards(b))
+        })
+    else {
+        sfx.write(PlaySfx(Sfx::Invalid));
+        return;
+    };
+
+    let to = level.data.cell_center(target);
+    commands.entity(trigger.target()).insert(Slide {
+        from: transform.translation,
+        to: Vec3::new(to.x, transform.translation.y, to.y),
+        timer: Timer::from_seconds(SLIDE_TIME, TimerMode::Once),
+    });
+    grid.swap(cell.0, target);
+    cell.0 = target;
+    moves.0 += 1;
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
+
+fn animate_slides(
+    mut commands: Commands,
+    mut slides: Query<(Entity, &mut Slide, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut slide, mut transform) in &mut slides {
+        slide.timer.tick(time.delta());
+        let t = slide.timer.fraction();
+        transform.translation = slide
+            .from
+            .lerp(slide.to, EaseFunction::CubicInOut.sample_clamped(t));
+        if slide.timer.finished() {
+            commands.entity(entity).remove::<Slide>();
+        }
+    }
+}
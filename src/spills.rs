@@ -0,0 +1,180 @@
+//! Puddles and drips where fluid spills out of the network
+
+use crate::camera::LevelCamera;
+use crate::flow::{Spill, Spills};
+use crate::game::GameEntity;
+use crate::level::{GridShape, LevelGrid, TILE_SIZE};
+use crate::options::Settings;
+use crate::pipes::{self, Fluids, Pipe};
+use bevy::prelude::*;
+
+pub struct SpillsPlugin;
+
+impl Plugin for SpillsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_spill_assets)
+            .add_systems(Update, (spawn_puddles, grow_puddles, animate_drips).chain());
+    }
+}
+
+/// Seconds between drips from each spilling side.
+const DRIP_INTERVAL: f32 = 0.15;
+
+/// How fast drips fall, in world units per second.
+const DRIP_SPEED: f32 = 3.;
+
+/// Seconds a drip lives, about the time it takes to fall into its puddle.
+const DRIP_TIME: f32 = 0.15;
+
+/// Puddle radius per unit of spilled volume, before it levels off.
+const PUDDLE_GROWTH: f32 = 1.2;
+
+/// Largest puddle radius, in world units.
+const MAX_PUDDLE_RADIUS: f32 = 1.8;
+
+/// Height of puddles below the pipe centers.
+const PUDDLE_DEPTH: f32 = 0.45;
+
+#[derive(Resource, Debug)]
+struct SpillAssets {
+    puddle: Handle<Mesh>,
+    drip: Handle<Mesh>,
+}
+
+/// Puddle growing where a spill comes out, with the index of the spill in [`Spills`].
+#[derive(Component, Debug)]
+struct Puddle {
+    spill: usize,
+    /// Spilled volume the puddle was last sized for
+    volume: f32,
+    drip_timer: Timer,
+}
+
+/// Drop of fluid falling off a spilling side.
+#[derive(Component, Debug)]
+struct Drip(Timer);
+
+fn initialize_spill_assets(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(SpillAssets {
+        puddle: meshes.add(Cylinder::new(1., 0.02)),
+        drip: meshes.add(Sphere::new(0.08)),
+    });
+}
+
+/// World position of the edge a spill comes out of.
+fn spill_position(spill: &Spill, pipe: &GlobalTransform, shape: GridShape) -> Vec3 {
+    let offset = shape.side_direction(spill.side) * TILE_SIZE / 2.;
+    pipe.translation() + Vec3::new(offset.x, 0., offset.y)
+}
+
+/// Spawns a puddle for each new spill and nudges the camera over to it.
+///
+/// The spilling side also gets an icon of the fluid, so spills can be told apart without colour.
+fn spawn_puddles(
+    mut commands: Commands,
+    spills: Option<Res<Spills>>,
+    puddles: Query<&Puddle>,
+    grid: Option<Res<LevelGrid>>,
+    pipes: Query<(&GlobalTransform, &Pipe)>,
+    fluids: Res<Fluids>,
+    assets: Res<SpillAssets>,
+    settings: Res<Settings>,
+    mut cameras: Query<&mut LevelCamera>,
+) {
+    let (Some(spills), Some(grid)) = (spills, grid) else {
+        return;
+    };
+    if !spills.is_changed() {
+        return;
+    }
+    for (index, spill) in spills.0.iter().enumerate() {
+        if puddles.iter().any(|puddle| puddle.spill == index) {
+            continue;
+        }
+        let Some((entity, (transform, pipe))) = grid
+            .get(spill.cell)
+            .and_then(|entity| Some((entity, pipes.get(entity).ok()?)))
+        else {
+            continue;
+        };
+        let Some(fluid) = fluids.get(&spill.fluid) else {
+            continue;
+        };
+        let position = spill_position(spill, transform, grid.shape);
+        commands.spawn((
+            Puddle {
+                spill: index,
+                volume: 0.,
+                drip_timer: Timer::from_seconds(DRIP_INTERVAL, TimerMode::Repeating),
+            },
+            Mesh3d(assets.puddle.clone()),
+            MeshMaterial3d(fluid.material.clone()),
+            Transform::from_translation(position - Vec3::Y * PUDDLE_DEPTH).with_scale(Vec3::ZERO),
+            GameEntity,
+        ));
+        let edge = pipes::model_side_direction(pipe.local_side(spill.side), pipe.sides());
+        commands.entity(entity).with_child(pipes::fluid_icon(
+            fluid,
+            &settings,
+            edge * TILE_SIZE / 2.,
+        ));
+        for mut camera in &mut cameras {
+            camera.nudge = Some(position.xz());
+        }
+    }
+}
+
+/// Widens the puddles with their spilled volume, dripping while the fluid still pours out.
+fn grow_puddles(
+    mut commands: Commands,
+    spills: Option<Res<Spills>>,
+    mut puddles: Query<(
+        &mut Puddle,
+        &mut Transform,
+        &MeshMaterial3d<StandardMaterial>,
+    )>,
+    assets: Res<SpillAssets>,
+    time: Res<Time>,
+) {
+    let Some(spills) = spills else {
+        return;
+    };
+    for (mut puddle, mut transform, material) in &mut puddles {
+        let Some(spill) = spills.0.get(puddle.spill) else {
+            continue;
+        };
+        if spill.volume <= puddle.volume {
+            continue;
+        }
+        puddle.volume = spill.volume;
+        let radius = (spill.volume.sqrt() * PUDDLE_GROWTH).min(MAX_PUDDLE_RADIUS);
+        transform.scale = Vec3::new(radius, 1., radius);
+
+        puddle.drip_timer.tick(time.delta());
+        if puddle.drip_timer.just_finished() {
+            let edge = transform.translation + Vec3::Y * PUDDLE_DEPTH;
+            commands.spawn((
+                Drip(Timer::from_seconds(DRIP_TIME, TimerMode::Once)),
+                Mesh3d(assets.drip.clone()),
+                material.clone(),
+                Transform::from_translation(edge),
+                GameEntity,
+            ));
+        }
+    }
+}
+
+/// Lets drips fall into their puddle, then vanish.
+fn animate_drips(
+    mut commands: Commands,
+    mut drips: Query<(Entity, &mut Drip, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut drip, mut transform) in &mut drips {
+        drip.0.tick(time.delta());
+        transform.translation.y -= DRIP_SPEED * time.delta_secs();
+        if drip.0.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
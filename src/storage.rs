@@ -0,0 +1,38 @@
+//! Player data kept in RON files in the working directory between runs
+//!
+//! Web builds have no file system, so there nothing is read back and nothing outlasts the session.
+
+use bevy::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Reads a saved value, starting afresh if there is none or it can't be read.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load<T: DeserializeOwned + Default>(path: &str) -> T {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return T::default();
+    };
+    ron::from_str(&text).unwrap_or_else(|error| {
+        warn!("Couldn't read {}: {}", path, error);
+        T::default()
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load<T: DeserializeOwned + Default>(_path: &str) -> T {
+    T::default()
+}
+
+/// Writes a value over its saved copy, warning if that fails.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save<T: Serialize>(path: &str, value: &T) {
+    let saved = ron::ser::to_string_pretty(value, default())
+        .map_err(|error| error.to_string())
+        .and_then(|text| std::fs::write(path, text).map_err(|error| error.to_string()));
+    if let Err(error) = saved {
+        warn!("Couldn't save {}: {}", path, error);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save<T: Serialize>(_path: &str, _value: &T) {}
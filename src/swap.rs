@@ -0,0 +1,262 @@
+//! Dragging pipes onto other cells to swap them, on levels that allow it
+
+use crate::AppState;
+use crate::audio::{PlaySfx, Sfx};
+use crate::camera::LevelCamera;
+use crate::game::{self, GameEntity, Moves, PipeGameState};
+use crate::level::{CurrentLevel, GridCell, Level, LevelGrid, TILE_SIZE};
+use crate::pipes::Pipe;
+use bevy::prelude::*;
+
+pub struct SwapPlugin;
+
+impl Plugin for SwapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_drop_marker)
+            .add_systems(OnExit(AppState::InGame), cleanup)
+            .add_observer(start_drag)
+            .add_observer(drag_pipe)
+            .add_observer(end_drag);
+    }
+}
+
+/// How far a dragged pipe is lifted off the grid.
+const DRAG_LIFT: f32 = 0.8;
+
+/// Pipe being dragged, with the position it returns to if the drop is rejected.
+#[derive(Component, Debug)]
+pub struct Dragged {
+    origin: Vec3,
+}
+
+/// Highlight on the cell a dragged pipe would be dropped on.
+#[derive(Component, Debug)]
+struct DropMarker;
+
+#[derive(Resource, Debug)]
+struct DropMarkerAssets {
+    mesh: Handle<Mesh>,
+    valid: Handle<StandardMaterial>,
+    invalid: Handle<StandardMaterial>,
+}
+
+fn initialize_drop_marker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut marker_material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        })
+    };
+    commands.insert_resource(DropMarkerAssets {
+        mesh: meshes.add(Plane3d::default().mesh().size(TILE_SIZE, TILE_SIZE)),
+        valid: marker_material(Color::srgba(0.3, 1.0, 0.4, 0.35)),
+        invalid: marker_material(Color::srgba(1.0, 0.2, 0.2, 0.35)),
+    });
+}
+
+fn cleanup(mut commands: Commands, markers: Query<Entity, With<DropMarker>>) {
+    for entity in &markers {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Whether pipes can be swapped right now: the level allows it and the player is solving.
+fn swapping_allowed(
+    levels: &Assets<Level>,
+    current_level: &CurrentLevel,
+    game_state: Option<&State<PipeGameState>>,
+) -> bool {
+    game::can_interact(game_state)
+        && levels
+            .get(&current_level.0)
+            .is_some_and(|level| level.allow_swap)
+}
+
+/// Pipes without fluid that the player hasn't been told to leave alone.
+fn movable(pipe: &Pipe) -> bool {
+    !pipe.locked && !pipe.has_fluid()
+}
+
+/// Point on the grid plane under the pointer.
+fn pointer_on_grid(
+    cameras: &Query<(&Camera, &GlobalTransform), With<LevelCamera>>,
+    viewport_position: Vec2,
+) -> Option<Vec3> {
+    let (camera, camera_transform) = cameras.single().ok()?;
+    let ray = camera
+        .viewport_to_world(camera_transform, viewport_position)
+        .ok()?;
+    let distance = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))?;
+    Some(ray.get_point(distance))
+}
+
+fn start_drag(
+    trigger: Trigger<Pointer<DragStart>>,
+    mut commands: Commands,
+    mut pipes: Query<(&Pipe, &mut Transform), Without<Dragged>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    marker: Res<DropMarkerAssets>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    let Some(current_level) = current_level else {
+        return;
+    };
+    if !swapping_allowed(&levels, &current_level, game_state.as_deref()) {
+        return;
+    }
+    let Ok((pipe, mut transform)) = pipes.get_mut(trigger.target()) else {
+        return;
+    };
+    if !movable(pipe) {
+        return;
+    }
+
+    commands.entity(trigger.target()).insert(Dragged {
+        origin: transform.translation,
+    });
+    commands.spawn((
+        Mesh3d(marker.mesh.clone()),
+        MeshMaterial3d(marker.valid.clone()),
+        Transform::from_translation(transform.translation.with_y(0.05)),
+        DropMarker,
+        GameEntity,
+    ));
+    transform.translation.y += DRAG_LIFT;
+}
+
+/// Moves a dragged pipe with the pointer and marks the cell it would land on.
+fn drag_pipe(
+    trigger: Trigger<Pointer<Drag>>,
+    mut pipes: Query<&mut Transform, (With<Dragged>, Without<DropMarker>)>,
+    mut markers: Query<
+        (
+            &mut Transform,
+            &mut MeshMaterial3d<StandardMaterial>,
+            &mut Visibility,
+        ),
+        With<DropMarker>,
+    >,
+    tiles: Query<&Pipe>,
+    cameras: Query<(&Camera, &GlobalTransform), With<LevelCamera>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    grid: Option<Res<LevelGrid>>,
+    marker: Res<DropMarkerAssets>,
+) {
+    let Ok(mut transform) = pipes.get_mut(trigger.target()) else {
+        return;
+    };
+    let (Some(current_level), Some(grid)) = (current_level, grid) else {
+        return;
+    };
+    let Some(point) = pointer_on_grid(&cameras, trigger.event().pointer_location.position) else {
+        return;
+    };
+    transform.translation = point.with_y(DRAG_LIFT);
+
+    let Some(level) = levels.get(&current_level.0) else {
+        return;
+    };
+    let target = level.data.cell_at(point.xz());
+    for (mut marker_transform, mut material, mut visibility) in &mut markers {
+        let Some(cell) = target else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        let center = level.data.cell_center(cell);
+        marker_transform.translation = Vec3::new(center.x, 0.05, center.y);
+        let valid = grid.get(cell).is_none_or(|entity| {
+            entity == trigger.target() || tiles.get(entity).is_ok_and(movable)
+        });
+        material.0 = if valid {
+            marker.valid.clone()
+        } else {
+            marker.invalid.clone()
+        };
+        *visibility = Visibility::Inherited;
+    }
+}
+
+/// Swaps the dragged pipe with whatever is in the cell it was dropped on.
+///
+/// Drops outside the grid, on obstacles, on locked pipes or on pipes with fluid are rejected, and
+/// the pipe returns to where it was picked up.
+fn end_drag(
+    trigger: Trigger<Pointer<DragEnd>>,
+    mut commands: Commands,
+    dragged: Query<&Dragged>,
+    mut tiles: Query<(&Pipe, &mut GridCell, &mut Transform)>,
+    markers: Query<Entity, With<DropMarker>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<LevelCamera>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    grid: Option<ResMut<LevelGrid>>,
+    moves: Option<ResMut<Moves>>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    let entity = trigger.target();
+    let Ok(dragged) = dragged.get(entity) else {
+        return;
+    };
+    let (Some(current_level), Some(mut grid), Some(mut moves)) = (current_level, grid, moves)
+    else {
+        return;
+    };
+    let origin = dragged.origin;
+    commands.entity(entity).remove::<Dragged>();
+    for marker in &markers {
+        commands.entity(marker).despawn();
+    }
+
+    let Ok((_, &GridCell(from), _)) = tiles.get(entity) else {
+        return;
+    };
+    let target = pointer_on_grid(&cameras, trigger.event().pointer_location.position)
+        .zip(levels.get(&current_level.0))
+        .and_then(|(point, level)| level.data.cell_at(point.xz()).map(|cell| (cell, level)));
+    let Some((to, level)) = target.filter(|(to, _)| *to != from) else {
+        // Dropped back on its own cell, or off the grid
+        if let Ok((_, _, mut transform)) = tiles.get_mut(entity) {
+            transform.translation = origin;
+        }
+        if target.is_none() {
+            sfx.write(PlaySfx(Sfx::Invalid));
+        }
+        return;
+    };
+
+    // Neither pipe may have fluid, which can reach the dragged one while it is in the air
+    let other = grid.get(to);
+    if tiles.get(entity).is_ok_and(|(pipe, _, _)| !movable(pipe))
+        || other.is_some_and(|other| !tiles.get(other).is_ok_and(|(pipe, _, _)| movable(pipe)))
+    {
+        if let Ok((_, _, mut transform)) = tiles.get_mut(entity) {
+            transform.translation = origin;
+        }
+        sfx.write(PlaySfx(Sfx::Invalid));
+        return;
+    }
+
+    for (tile, cell) in [(Some(entity), to), (other, from)] {
+        let Some(Ok((_, mut grid_cell, mut transform))) = tile.map(|tile| tiles.get_mut(tile))
+        else {
+            continue;
+        };
+        let center = level.data.cell_center(cell);
+        grid_cell.0 = cell;
+        transform.translation = Vec3::new(center.x, 0., center.y);
+    }
+    grid.swap(from, to);
+    moves.0 += 1;
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
@@ -0,0 +1,255 @@
+//! UI theme asset and styling helpers
+
+use crate::AppState;
+use crate::assets::UiAssets;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::ecs::system::SystemParam;
+use bevy::input_focus::InputDispatchPlugin;
+use bevy::input_focus::tab_navigation::TabNavigationPlugin;
+use bevy::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((InputDispatchPlugin, TabNavigationPlugin))
+            .init_asset::<Theme>()
+            .init_asset_loader::<ThemeLoader>()
+            .add_systems(OnExit(AppState::LoadingAssets), select_default_theme)
+            .add_systems(
+                Update,
+                apply_theme.run_if(resource_exists::<ActiveTheme>.and(theme_changed)),
+            );
+    }
+}
+
+/// Colours, sizes and fonts shared by all UI builders.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct Theme {
+    /// Display name, shown in the options menu
+    pub name: String,
+    pub button: ButtonPalette,
+    pub title_color: ThemeColor,
+    pub body_color: ThemeColor,
+    /// Button border width in pixels
+    pub border_width: f32,
+    /// Button corner radius in pixels
+    pub border_radius: f32,
+    pub font_sizes: FontSizes,
+    pub fonts: ThemeFonts,
+}
+
+/// Button colours for each interaction state.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ButtonPalette {
+    pub normal: ButtonColors,
+    pub hovered: ButtonColors,
+    pub pressed: ButtonColors,
+    pub focused: ButtonColors,
+    pub disabled: ButtonColors,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ButtonColors {
+    pub background: ThemeColor,
+    pub border: ThemeColor,
+    pub text: ThemeColor,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FontSizes {
+    pub title: f32,
+    pub button: f32,
+    pub body: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThemeFonts {
+    pub title: FontKey,
+    pub button: FontKey,
+    pub body: FontKey,
+}
+
+/// Reference to one of the fonts in [`UiAssets`].
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum FontKey {
+    NunitoBlack,
+    NunitoRegular,
+    Knewave,
+}
+
+/// Colour written as a hex string (`"#4c92d4"`) in theme files.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = bevy::color::HexColorError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(ThemeColor(Srgba::hex(value)?.into()))
+    }
+}
+
+impl From<ThemeColor> for Color {
+    fn from(value: ThemeColor) -> Self {
+        value.0
+    }
+}
+
+/// The theme currently used to style the UI. Replace the handle to swap themes at runtime.
+#[derive(Resource, Debug)]
+pub struct ActiveTheme(pub Handle<Theme>);
+
+/// Role of a piece of themed text, which decides its font, size and colour.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextRole {
+    Title,
+    Button,
+    Body,
+}
+
+/// Marker for buttons whose border and corners follow the active theme.
+#[derive(Component, Debug)]
+pub struct ThemedButton;
+
+/// Access to the active theme and its fonts for UI builders.
+#[derive(SystemParam)]
+pub struct UiTheme<'w> {
+    assets: Res<'w, UiAssets>,
+    themes: Res<'w, Assets<Theme>>,
+    active: Option<Res<'w, ActiveTheme>>,
+}
+
+impl UiTheme<'_> {
+    /// The active theme, or the default theme asset until one is selected or while a swapped in
+    /// theme is still loading.
+    ///
+    /// The default theme is loaded with the [`UiAssets`], so it is always there for UI builders.
+    pub fn get(&self) -> &Theme {
+        self.active
+            .as_ref()
+            .and_then(|active| self.themes.get(&active.0))
+            .or_else(|| self.themes.get(&self.assets.default_theme))
+            .expect("default theme is loaded with the UI assets")
+    }
+
+    pub fn font(&self, role: TextRole) -> Handle<Font> {
+        let fonts = &self.get().fonts;
+        let key = match role {
+            TextRole::Title => fonts.title,
+            TextRole::Button => fonts.button,
+            TextRole::Body => fonts.body,
+        };
+        self.assets.font(key)
+    }
+
+    pub fn font_size(&self, role: TextRole) -> f32 {
+        let sizes = &self.get().font_sizes;
+        match role {
+            TextRole::Title => sizes.title,
+            TextRole::Button => sizes.button,
+            TextRole::Body => sizes.body,
+        }
+    }
+
+    pub fn text_color(&self, role: TextRole) -> Color {
+        let theme = self.get();
+        match role {
+            TextRole::Title => theme.title_color.into(),
+            TextRole::Button => theme.button.normal.text.into(),
+            TextRole::Body => theme.body_color.into(),
+        }
+    }
+
+    /// Text bundle styled for the given role.
+    pub fn text(&self, text: &str, role: TextRole) -> impl Bundle + use<> {
+        (
+            Text::new(text),
+            TextFont {
+                font: self.font(role),
+                font_size: self.font_size(role),
+                ..default()
+            },
+            TextColor(self.text_color(role)),
+            role,
+        )
+    }
+
+    pub fn border(&self) -> UiRect {
+        UiRect::all(Val::Px(self.get().border_width))
+    }
+
+    pub fn border_radius(&self) -> BorderRadius {
+        BorderRadius::all(Val::Px(self.get().border_radius))
+    }
+}
+
+fn select_default_theme(mut commands: Commands, assets: Res<UiAssets>) {
+    commands.insert_resource(ActiveTheme(assets.default_theme.clone()));
+}
+
+fn theme_changed(active: Res<ActiveTheme>, mut events: EventReader<AssetEvent<Theme>>) -> bool {
+    let modified = events
+        .read()
+        .any(|event| event.is_modified(&active.0) || event.is_loaded_with_dependencies(&active.0));
+    active.is_changed() || modified
+}
+
+/// Restyles existing UI when the active theme is swapped or its asset is reloaded.
+///
+/// Button colours are handled by [`crate::menu::update_button_color`].
+fn apply_theme(
+    theme: UiTheme,
+    mut texts: Query<(&TextRole, &mut TextFont, &mut TextColor)>,
+    mut buttons: Query<(&mut Node, &mut BorderRadius), With<ThemedButton>>,
+) {
+    info!("Applying UI theme {}", theme.get().name);
+    for (role, mut font, mut color) in &mut texts {
+        font.font = theme.font(*role);
+        font.font_size = theme.font_size(*role);
+        if *role != TextRole::Button {
+            color.0 = theme.text_color(*role);
+        }
+    }
+
+    for (mut node, mut radius) in &mut buttons {
+        node.border = theme.border();
+        *radius = theme.border_radius();
+    }
+}
+
+#[derive(Default, Debug)]
+struct ThemeLoader;
+
+#[derive(Debug, Error)]
+enum ThemeError {
+    #[error("I/O error while loading theme: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse theme: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+impl AssetLoader for ThemeLoader {
+    type Asset = Theme;
+    type Settings = ();
+    type Error = ThemeError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["theme.ron"]
+    }
+}
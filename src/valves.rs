@@ -0,0 +1,180 @@
+//! Gate valves and diverters the player operates while preparing and during the flow
+
+use crate::audio::{PlaySfx, Sfx};
+use crate::game::{self, Moves, PipeGameState};
+use crate::level::{CurrentLevel, GameMode, Level};
+use crate::pipes::{self, Pipe, Valve};
+use crate::swap::Dragged;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct ValvesPlugin;
+
+impl Plugin for ValvesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, initialize_valve_indicators)
+            .add_systems(
+                Update,
+                (
+                    auto_toggle_valves.run_if(in_state(PipeGameState::Flowing)),
+                    spawn_valve_indicators,
+                    update_valve_indicators,
+                )
+                    .chain(),
+            )
+            .add_observer(toggle_valve);
+    }
+}
+
+/// Flips a valve on its own during the flow, at the interval set with the `interval` property of
+/// a map object on it.
+#[derive(Component, Debug)]
+pub struct ValveTimer(pub Timer);
+
+/// Handle on a valve, showing whether a gate is open or which way a diverter points.
+#[derive(Component, Debug)]
+struct ValveIndicator;
+
+#[derive(Resource, Debug)]
+struct ValveIndicatorAssets {
+    handle: Handle<Mesh>,
+    arrow: Handle<Mesh>,
+    open: Handle<StandardMaterial>,
+    closed: Handle<StandardMaterial>,
+}
+
+fn initialize_valve_indicators(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut indicator_material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        })
+    };
+    commands.insert_resource(ValveIndicatorAssets {
+        handle: meshes.add(Cuboid::new(0.9, 0.15, 0.25)),
+        arrow: meshes.add(Triangle2d::new(
+            Vec2::new(0., 0.45),
+            Vec2::new(-0.3, -0.15),
+            Vec2::new(0.3, -0.15),
+        )),
+        open: indicator_material(Color::srgb(0.2, 0.9, 0.3)),
+        closed: indicator_material(Color::srgb(0.9, 0.2, 0.2)),
+    });
+}
+
+/// Operates a clicked valve, also with fluid running through it, see [`Pipe::toggle_valve`].
+fn toggle_valve(
+    trigger: Trigger<Pointer<Click>>,
+    mut pipes: Query<&mut Pipe, Without<Dragged>>,
+    game_state: Option<Res<State<PipeGameState>>>,
+    moves: Option<ResMut<Moves>>,
+    levels: Res<Assets<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    if trigger.event().button != PointerButton::Primary {
+        return;
+    }
+    // A click on a sliding puzzle moves the pipe instead
+    if current_level
+        .and_then(|current_level| levels.get(&current_level.0))
+        .is_some_and(|level| level.mode == GameMode::Sliding)
+    {
+        return;
+    }
+    if !game::can_interact(game_state.as_deref()) {
+        return;
+    }
+    let Ok(mut pipe) = pipes.get_mut(trigger.target()) else {
+        return;
+    };
+    if pipe.valve.is_none() {
+        return;
+    }
+
+    pipe.toggle_valve();
+    if let Some(mut moves) = moves {
+        moves.0 += 1;
+    }
+    sfx.write(PlaySfx(Sfx::PipeRotate));
+}
+
+fn auto_toggle_valves(
+    mut valves: Query<(&mut Pipe, &mut ValveTimer)>,
+    time: Res<Time>,
+    mut sfx: EventWriter<PlaySfx>,
+) {
+    let mut toggled = false;
+    for (mut pipe, mut timer) in &mut valves {
+        if timer.0.tick(time.delta()).just_finished() {
+            pipe.toggle_valve();
+            toggled = true;
+        }
+    }
+    if toggled {
+        sfx.write(PlaySfx(Sfx::PipeRotate));
+    }
+}
+
+fn spawn_valve_indicators(
+    mut commands: Commands,
+    pipes: Query<(Entity, &Pipe), Added<Pipe>>,
+    assets: Res<ValveIndicatorAssets>,
+) {
+    for (entity, pipe) in &pipes {
+        let mesh = match pipe.valve {
+            Some(Valve::Gate { .. }) => assets.handle.clone(),
+            Some(Valve::Diverter { .. }) => assets.arrow.clone(),
+            None => continue,
+        };
+        commands.entity(entity).with_child((
+            ValveIndicator,
+            Mesh3d(mesh),
+            MeshMaterial3d(assets.open.clone()),
+            Transform::from_xyz(0., 1.0, 0.),
+        ));
+    }
+}
+
+/// Turns gate handles across the pipe when closed, and points diverter arrows at their outlet.
+fn update_valve_indicators(
+    pipes: Query<(&Pipe, &Children), Changed<Pipe>>,
+    mut indicators: Query<
+        (&mut Transform, &mut MeshMaterial3d<StandardMaterial>),
+        With<ValveIndicator>,
+    >,
+    assets: Res<ValveIndicatorAssets>,
+) {
+    for (pipe, children) in &pipes {
+        let (rotation, open) = match &pipe.valve {
+            // The handle lies along the pipe when open, like a ball valve lever
+            Some(Valve::Gate { open: true }) => (Quat::IDENTITY, true),
+            Some(Valve::Gate { open: false }) => (Quat::from_rotation_y(FRAC_PI_2), false),
+            Some(Valve::Diverter {
+                outlets, selected, ..
+            }) => (
+                Quat::from_rotation_arc(
+                    Vec3::NEG_Z,
+                    pipes::model_side_direction(outlets[*selected], pipe.sides()),
+                ) * Quat::from_rotation_x(-FRAC_PI_2),
+                true,
+            ),
+            None => continue,
+        };
+        for child in children {
+            if let Ok((mut transform, mut material)) = indicators.get_mut(*child) {
+                transform.rotation = rotation;
+                material.0 = if open {
+                    assets.open.clone()
+                } else {
+                    assets.closed.clone()
+                };
+            }
+        }
+    }
+}